@@ -0,0 +1,19 @@
+#![no_main]
+
+use dns_r::dns::DnsPacket;
+use libfuzzer_sys::fuzz_target;
+
+// Any input must produce Ok or Err, never a panic. Whatever parses must also survive being written back out
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = DnsPacket::parse_lenient(data) {
+        let _ = packet.serialize_to_bytes();
+
+        for record in packet.answers.iter().chain(&packet.authorities).chain(&packet.additionals) {
+            if let Ok(rdata) = record.rdata() {
+                let _ = rdata.to_text();
+            }
+        }
+    }
+
+    let _ = DnsPacket::parse(data);
+});
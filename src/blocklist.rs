@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::error::DnsError;
+use crate::name::visit_name_labels;
+
+
+/// Names the server refuses to resolve for real, answering them with its sinkhole address instead. An entry blocks
+/// the name itself and everything below it, so `ads.example.com` also covers `tracker.ads.example.com`.
+/// Entries are kept in a tree of labels read from the root down, so a lookup walks at most one node per label
+/// of the queried name however long the list is. Matching ignores case and a trailing dot
+pub struct Blocklist {
+    root: LabelNode,
+    len: usize,                     // Entries inserted, not counting duplicates
+}
+
+/// One label in the tree, with the labels found to its left in blocked names as children
+#[derive(Default)]
+struct LabelNode {
+    children: HashMap<Vec<u8>, LabelNode>,
+    blocked: bool,                  // This node's name is an entry, so it and every name below it are blocked
+}
+
+impl Blocklist {
+    pub fn new() -> Blocklist {
+        Blocklist {
+            root: LabelNode::default(),
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, name: &str) {
+
+        let mut node = &mut self.root;
+        for label in labels_from_root(name) {
+            node = node.children.entry(label).or_default();
+        }
+
+        if !node.blocked {
+            node.blocked = true;
+            self.len += 1;
+        }
+    }
+
+    /// Add every name listed in a file: one per line, with blank lines and `#` comments skipped. Hosts file lines
+    /// such as `0.0.0.0 ads.example.com` work too, as only the last field of a line is taken.
+    /// Returns how many names were added
+    pub fn load(&mut self, path: impl AsRef<Path>) -> Result<usize, DnsError> {
+
+        let text = fs::read_to_string(path)?;
+        let mut loaded = 0;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some(name) = line.split_whitespace().last() else {
+                continue;
+            };
+
+            self.insert(name);
+            loaded += 1;
+        }
+
+        Ok(loaded)
+    }
+
+    /// True when `name` or any name above it is an entry
+    pub fn contains(&self, name: &str) -> bool {
+
+        let mut node = &self.root;
+        for label in labels_from_root(name) {
+            if node.blocked {
+                return true;
+            }
+            match node.children.get(&label) {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+
+        node.blocked
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Default for Blocklist {
+    fn default() -> Blocklist {
+        Blocklist::new()
+    }
+}
+
+
+/// The lowercased labels of a name, starting next to the root: `www.example.com` gives com, example, www
+fn labels_from_root(name: &str) -> Vec<Vec<u8>> {
+
+    let mut labels = Vec::new();
+    visit_name_labels(name, |label| labels.push(label.to_ascii_lowercase()));
+    labels.reverse();
+
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_block_the_name_and_its_subdomains_only() {
+        let mut blocklist = Blocklist::new();
+        blocklist.insert("ads.example.com");
+        blocklist.insert("ADS.example.com.");
+        assert_eq!(blocklist.len(), 1);
+
+        assert!(blocklist.contains("ads.example.com"));
+        assert!(blocklist.contains("Ads.Example.Com."));
+        assert!(blocklist.contains("tracker.ads.example.com"));
+
+        assert!(!blocklist.contains("example.com"));
+        assert!(!blocklist.contains("www.example.com"));
+        assert!(!blocklist.contains("badads.example.com"));
+        assert!(!blocklist.contains("ads.example.org"));
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::dns::DnsPacket;
+
+
+/// Responses kept until their TTL runs out, so repeated questions don't go back upstream.
+/// Holds at most max_entries responses; when full, the least recently used one makes room for a new one
+pub struct Cache {
+    entries: HashMap<String, CacheEntry>,
+    pub max_entries: usize,             // Most responses held at once (0 disables caching)
+    clock: u64,                         // Bumped on every get and insert, to order entries by their last use
+}
+
+struct CacheEntry {
+    response: DnsPacket,
+    stored_at: Instant,
+    expires_at: Instant,
+    last_used: u64,                     // Value of the cache clock when this entry was last stored or returned
+}
+
+impl Cache {
+    pub fn new(max_entries: usize) -> Cache {
+        Cache {
+            entries: HashMap::new(),
+            max_entries,
+            clock: 0,
+        }
+    }
+
+    /// Fetch a live response. TTLs in the copy handed back are reduced by the time it has spent in the cache;
+    /// everything else, record order included, is exactly as it was stored
+    pub fn get(&mut self, key: &str) -> Option<DnsPacket> {
+
+        let now = Instant::now();
+
+        self.clock += 1;
+
+        let entry = self.entries.get_mut(key)?;
+        if now >= entry.expires_at {
+            self.entries.remove(key);
+            return None;
+        }
+        entry.last_used = self.clock;
+
+        let elapsed = now.duration_since(entry.stored_at).as_secs() as u32;
+        let mut response = entry.response.clone();
+        for answer in response.answers.iter_mut().chain(&mut response.authorities).chain(&mut response.additionals) {
+            answer.resource_record.ttl = answer.resource_record.ttl.saturating_sub(elapsed);
+        }
+
+        Some(response)
+    }
+
+    /// Store a response for `ttl` seconds. When the cache is full, expired entries are cleared out first and if
+    /// that frees nothing the least recently used entry is evicted
+    pub fn insert(&mut self, key: String, response: DnsPacket, ttl: u32) {
+
+        if ttl == 0 || self.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        self.clock += 1;
+
+        if self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            self.entries.retain(|_, entry| entry.expires_at > now);
+        }
+        while self.entries.len() >= self.max_entries && !self.entries.contains_key(&key) {
+            let Some(oldest) = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone()) else { break };
+            self.entries.remove(&oldest);
+        }
+
+        let entry = CacheEntry {
+            response,
+            stored_at: now,
+            expires_at: now + Duration::from_secs(ttl as u64),
+            last_used: self.clock,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_cache_evicts_the_least_recently_used_entry() {
+        let mut cache = Cache::new(2);
+
+        cache.insert("a".to_string(), DnsPacket::new(), 300);
+        cache.insert("b".to_string(), DnsPacket::new(), 300);
+        cache.insert("c".to_string(), DnsPacket::new(), 300);
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+
+        // b was just used, so c is now the oldest
+        cache.insert("d".to_string(), DnsPacket::new(), 300);
+        assert!(cache.get("c").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("d").is_some());
+    }
+}
@@ -2,13 +2,673 @@
 
 
 
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::net::{Ipv4Addr, Ipv6Addr};
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+use crate::edns::{ExtendedError, OptRecord};
+use crate::error::DnsError;
+use crate::name::{encode_name, name_matches, read_name, read_name_wire, remember_name_offset, split_first_label, visit_name_labels, wire_labels, write_name, write_name_compressed, MAX_POINTER_OFFSET};
+use crate::rdata::{canonical_rdata, RData};
+
+
+const MIN_QUESTION_LEN: usize = 5;      // Root name (1 byte) + type + class
+const MIN_RECORD_LEN: usize = 11;       // Root name (1 byte) + type + class + ttl + rdata length, with empty rdata
+const MAX_SLICE_NAMES: usize = 128;     // Name suffixes serialize_to_slice remembers as compression targets
+
+
+/// A complete DNS message: the header followed by the question, answer, authority and additional sections
+#[derive(Debug, Clone)]
 pub struct DnsPacket {
-    header: DnsHeader,
-    question: QuestionSection,
-    answer: AnswerSection,
+    pub header: DnsHeader,
+    pub questions: Vec<QuestionSection>,
+    pub answers: Vec<AnswerSection>,
+    pub authorities: Vec<AnswerSection>,
+    pub additionals: Vec<AnswerSection>,
+    pub edns: Option<OptRecord>,            // OPT pseudo-record, kept out of `additionals` but still counted and written there
+    pub compress_names: bool,               // Write repeated owner names as pointers to their first copy. Clear it for clients that mishandle compression
+}
+
+impl DnsPacket {
+    pub fn new() -> DnsPacket {
+        DnsPacket {
+            header: DnsHeader::new(),
+            questions: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+            edns: None,
+            compress_names: true,
+        }
+    }
+
+    /// Build a recursive (RD set) IN class query holding every given question, in order.
+    /// Most servers only answer single-question queries, so this is for tooling and unusual upstreams
+    pub fn query_multi(id: u16, questions: Vec<(String, RecordType)>) -> DnsPacket {
+
+        let mut query = DnsPacket::new();
+        query.header.id = id;
+        query.header.recursion_desired = true;
+
+        for (name, record_type) in questions {
+            query.questions.push(QuestionSection::new_query(&name, record_type, DnsClass::In));
+        }
+
+        query.header.question_count = query.questions.len() as u16;
+
+        query
+    }
+
+    /// A NOTIFY message (RFC 1996) telling a secondary server that `zone` has changed, so it should check the SOA
+    /// serial and transfer the zone if it is behind. The question is `<zone> SOA IN` and, as the RFC asks of
+    /// notifications sent by a primary, AA is set
+    pub fn notify(id: u16, zone: &str) -> DnsPacket {
+
+        let mut notify = DnsPacket::new();
+        notify.header.id = id;
+        notify.header.opcode = Opcode::Notify.into();
+        notify.header.authoritative_answer = true;
+
+        notify.questions.push(QuestionSection::new_query(zone, RecordType::Soa, DnsClass::In));
+        notify.header.question_count = 1;
+
+        notify
+    }
+
+    /// True for a dynamic UPDATE message (opcode 5, RFC 2136). UPDATE parses like any other message, but the four
+    /// sections mean something else: the questions hold the zone, answers the prerequisites, authorities the
+    /// updates, and the additional section stays additional data. Use update_zone, prerequisites and updates to
+    /// read them under their proper names
+    pub fn is_update(&self) -> bool {
+        Opcode::from(self.header.opcode) == Opcode::Update
+    }
+
+    /// The zone an UPDATE applies to: its single zone section entry, always of type SOA. None for other opcodes
+    pub fn update_zone(&self) -> Option<&QuestionSection> {
+        self.questions.first().filter(|_| self.is_update())
+    }
+
+    /// RRsets that must (or must not) exist for an UPDATE to go ahead. Empty for other opcodes
+    pub fn prerequisites(&self) -> &[AnswerSection] {
+        if self.is_update() { &self.answers } else { &[] }
+    }
+
+    /// Records an UPDATE adds or deletes. Class NONE deletes a record, class ANY a whole RRset. Empty for other opcodes
+    pub fn updates(&self) -> &[AnswerSection] {
+        if self.is_update() { &self.authorities } else { &[] }
+    }
+
+    /// Start the response to a query: same id, opcode, RD flag and questions, with QR set. Every other flag starts
+    /// clear, the reserved Z bit included, however the query had it. Answers are left for the caller to push
+    pub fn response_to(query: &DnsPacket) -> DnsPacket {
+
+        let mut response = DnsPacket::new();
+
+        response.header.id = query.header.id;
+        response.header.query_indicator = true;
+        response.header.opcode = query.header.opcode;
+        response.header.recursion_desired = query.header.recursion_desired;
+        response.header.question_count = query.questions.len() as u16;
+        response.questions = query.questions.clone();
+
+        response
+    }
+
+    /// The whole response to a query answered by `records`: response_to with each RData added as an answer owned
+    /// by the question's name, in order, with the given TTL. Flags such as AA are left for the caller to set
+    pub fn answer_with(query: &DnsPacket, records: Vec<RData>, ttl: u32) -> DnsPacket {
+
+        let mut response = DnsPacket::response_to(query);
+        let name = query.questions.first().map(|question| question.resource_record.name.as_str()).unwrap_or_default();
+
+        for rdata in records {
+            response.answers.push(AnswerSection::from_rdata(name, ttl, rdata));
+        }
+        response.header.answer_record_count = response.answers.len() as u16;
+
+        response
+    }
+
+    /// A bare error reply (QR set, no records) carrying `rcode`, for FORMERR, SERVFAIL, NOTIMP, REFUSED and the like.
+    /// The question is echoed when there is one - a FORMERR for an unparseable query may have none to echo
+    pub fn error_response(query_id: u16, question: Option<QuestionSection>, rcode: ResponseCode) -> DnsPacket {
+
+        let mut response = DnsPacket::new();
+
+        response.header.id = query_id;
+        response.header.query_indicator = true;
+        response.header.response_code = rcode.into();
+        response.questions.extend(question);
+        response.header.question_count = response.questions.len() as u16;
+
+        response
+    }
+
+    /// The Extended DNS Error carried in the OPT record, if the sender included one
+    pub fn extended_error(&self) -> Option<ExtendedError> {
+        self.edns.as_ref()?.extended_error()
+    }
+
+    /// True when the TC bit is set: the sender cut the message short to fit a UDP datagram and the
+    /// query should be repeated over TCP to get the full answer
+    pub fn is_truncated(&self) -> bool {
+        self.header.truncation
+    }
+
+    /// Copy of this packet with a different transaction id, for re-sending a query after a timeout
+    /// without letting a late reply to the first attempt be mistaken for the answer to the retry
+    pub fn with_new_id(&self, id: u16) -> DnsPacket {
+
+        let mut retry = self.clone();
+        retry.header.id = id;
+
+        retry
+    }
+
+    /// Size the EDNS padding option (RFC 7830) so the serialized message is a multiple of `block_size` bytes,
+    /// adding an OPT record if there is none. Over encrypted transports this hides the exact size of a message,
+    /// which would otherwise hint at the name asked about. RFC 8467 suggests 128 byte blocks for queries and 468
+    /// for responses. A block_size of 0 leaves the packet alone
+    pub fn pad_to_block(&mut self, block_size: usize) {
+
+        if block_size == 0 {
+            return;
+        }
+
+        self.edns.get_or_insert_with(OptRecord::new).options.retain(|option| option.code != OptRecord::PADDING_OPTION_CODE);
+
+        // The padding option's own 4 byte header counts towards the total before any padding bytes are added
+        let unpadded_len = self.serialize_to_bytes().len() + 4;
+        let padding = (block_size - unpadded_len % block_size) % block_size;
+
+        self.edns.get_or_insert_with(OptRecord::new).set_padding(padding);
+    }
+
+    /// Smallest TTL among the answer and authority records, or None when both sections are empty. A response kept
+    /// as a whole goes stale as soon as its shortest lived record does, so this is how long it may be cached.
+    /// Additional records are left out, they are hints rather than part of the answer
+    pub fn min_ttl(&self) -> Option<u32> {
+        self.answers.iter().chain(&self.authorities).map(|record| record.resource_record.ttl).min()
+    }
+
+    /// True when the answer section actually answers `question`: a record of the asked type owned by the asked name,
+    /// or by the end of a CNAME chain starting at it. Names are compared case-insensitively
+    pub fn answers_question(&self, question: &QuestionSection) -> bool {
+
+        let wanted_type = question.resource_record.record_type;
+        let mut name = question.resource_record.name.trim_end_matches('.').to_string();
+
+        // Each step follows a different CNAME record, so a looping chain ends after at most answers.len() steps
+        for _ in 0..=self.answers.len() {
+            let owned_here = |answer: &&AnswerSection| answer.resource_record.name.trim_end_matches('.').eq_ignore_ascii_case(&name);
+
+            if self.answers.iter().filter(owned_here).any(|answer| answer.resource_record.record_type == wanted_type) {
+                return true;
+            }
+
+            let alias = self.answers.iter().filter(owned_here).find_map(|answer| match answer.rdata() {
+                Ok(RData::Cname(target)) => Some(target),
+                _ => None,
+            });
+
+            match alias {
+                Some(target) => name = target.trim_end_matches('.').to_string(),
+                None => return false,
+            }
+        }
+
+        false
+    }
+
+    /// Remove the DNSSEC records (RRSIG, NSEC, NSEC3, DNSKEY) from every section, for clients that did not ask for
+    /// them. A type the question explicitly asked for is kept - a DNSKEY query still gets its DNSKEY answer
+    pub fn strip_dnssec(&mut self) {
+
+        let asked: Vec<u16> = self.questions.iter().map(|question| question.resource_record.record_type).collect();
+        let keep = |record: &AnswerSection| {
+            let record_type = record.resource_record.record_type;
+            asked.contains(&record_type)
+                || !matches!(RecordType::from(record_type), RecordType::Rrsig | RecordType::Nsec | RecordType::Nsec3 | RecordType::Dnskey)
+        };
+
+        self.answers.retain(keep);
+        self.authorities.retain(keep);
+        self.additionals.retain(keep);
+
+        self.header.answer_record_count = self.answers.len() as u16;
+        self.header.authority_record_count = self.authorities.len() as u16;
+        self.header.additional_record_count = (self.additionals.len() + self.edns.is_some() as usize) as u16;
+    }
+
+    /// Remove repeated answers (see ResourceRecord's PartialEq), keeping the first copy of each in its original position
+    pub fn dedup_answers(&mut self) {
+
+        let mut kept: Vec<AnswerSection> = Vec::with_capacity(self.answers.len());
+
+        for answer in self.answers.drain(..) {
+            if !kept.contains(&answer) {
+                kept.push(answer);
+            }
+        }
+
+        self.answers = kept;
+        self.header.answer_record_count = self.answers.len() as u16;
+    }
+
+    /// Every field that differs between two packets, one readable line each, such as "answer[0].ttl 300 != 60"
+    /// (this packet's value first). Empty when the packets carry the same message. Header counts are compared
+    /// as stored; rdata is shown in presentation format where it parses. Meant for tests and debugging
+    pub fn diff(&self, other: &DnsPacket) -> Vec<String> {
+
+        let mut differences = Vec::new();
+        let (ours, theirs) = (&self.header, &other.header);
+
+        note_difference(&mut differences, "header.id", ours.id, theirs.id);
+        note_difference(&mut differences, "header.qr", ours.query_indicator, theirs.query_indicator);
+        note_difference(&mut differences, "header.opcode", ours.opcode, theirs.opcode);
+        note_difference(&mut differences, "header.aa", ours.authoritative_answer, theirs.authoritative_answer);
+        note_difference(&mut differences, "header.tc", ours.truncation, theirs.truncation);
+        note_difference(&mut differences, "header.rd", ours.recursion_desired, theirs.recursion_desired);
+        note_difference(&mut differences, "header.ra", ours.recursion_available, theirs.recursion_available);
+        note_difference(&mut differences, "header.z", ours.reserved, theirs.reserved);
+        note_difference(&mut differences, "header.ad", ours.authentic_data, theirs.authentic_data);
+        note_difference(&mut differences, "header.cd", ours.check_disabled, theirs.check_disabled);
+        note_difference(&mut differences, "header.rcode", ours.response_code, theirs.response_code);
+        note_difference(&mut differences, "header.qdcount", ours.question_count, theirs.question_count);
+        note_difference(&mut differences, "header.ancount", ours.answer_record_count, theirs.answer_record_count);
+        note_difference(&mut differences, "header.nscount", ours.authority_record_count, theirs.authority_record_count);
+        note_difference(&mut differences, "header.arcount", ours.additional_record_count, theirs.additional_record_count);
+
+        note_difference(&mut differences, "questions.len", self.questions.len(), other.questions.len());
+        for (index, (ours, theirs)) in self.questions.iter().zip(&other.questions).enumerate() {
+            let (ours, theirs) = (&ours.resource_record, &theirs.resource_record);
+            note_difference(&mut differences, &format!("question[{}].name", index), &ours.name, &theirs.name);
+            note_difference(&mut differences, &format!("question[{}].type", index), RecordType::from(ours.record_type), RecordType::from(theirs.record_type));
+            note_difference(&mut differences, &format!("question[{}].class", index), DnsClass::from(ours.class), DnsClass::from(theirs.class));
+        }
+
+        for (section, ours, theirs) in [
+            ("answer", &self.answers, &other.answers),
+            ("authority", &self.authorities, &other.authorities),
+            ("additional", &self.additionals, &other.additionals),
+        ] {
+            note_difference(&mut differences, &format!("{}s.len", section), ours.len(), theirs.len());
+            for (index, (ours, theirs)) in ours.iter().zip(theirs).enumerate() {
+                diff_record(&mut differences, &format!("{}[{}]", section, index), ours, theirs);
+            }
+        }
+
+        match (&self.edns, &other.edns) {
+            (Some(ours), Some(theirs)) => {
+                note_difference(&mut differences, "edns.udp_payload_size", ours.udp_payload_size, theirs.udp_payload_size);
+                note_difference(&mut differences, "edns.extended_rcode", ours.extended_rcode, theirs.extended_rcode);
+                note_difference(&mut differences, "edns.version", ours.version, theirs.version);
+                note_difference(&mut differences, "edns.flags", ours.flags, theirs.flags);
+                note_difference(&mut differences, "edns.options", &ours.options, &theirs.options);
+            }
+            (ours, theirs) => note_difference(&mut differences, "edns.present", ours.is_some(), theirs.is_some()),
+        }
+
+        differences
+    }
+
+    /// The RRset of `name` and `record_type` in the canonical form DNSSEC signs (RFC 4034 section 6): every
+    /// matching record from any section, owner and embedded names lowercased and uncompressed, sorted by their
+    /// canonical rdata with duplicates removed, each written as name, type, class, TTL, rdata length and rdata.
+    /// TTLs are used as they are - set them to the RRSIG's original TTL first when building signing input
+    pub fn canonical_rrset_bytes(&self, name: &str, record_type: RecordType) -> Vec<u8> {
+
+        let owner = encode_name(name).to_ascii_lowercase();
+
+        let mut rrset: Vec<(&ResourceRecord, Vec<u8>)> = self.answers.iter()
+            .chain(&self.authorities)
+            .chain(&self.additionals)
+            .map(|answer| &answer.resource_record)
+            .filter(|record| RecordType::from(record.record_type) == record_type && encode_name(&record.name).to_ascii_lowercase() == owner)
+            .map(|record| (record, canonical_rdata(record_type, &record.rdata.encode())))
+            .collect();
+
+        // Rdata compares as a left-justified unsigned octet sequence, exactly how byte slices order
+        rrset.sort_by(|(_, ours), (_, theirs)| ours.cmp(theirs));
+        rrset.dedup_by(|(_, ours), (_, theirs)| ours == theirs);
+
+        let mut buffer_vec = Vec::new();
+        for (record, rdata) in rrset {
+            buffer_vec.extend_from_slice(&owner);
+            buffer_vec.extend_from_slice(&record.record_type.to_be_bytes());
+            buffer_vec.extend_from_slice(&record.class.to_be_bytes());
+            buffer_vec.extend_from_slice(&record.ttl.to_be_bytes());
+            buffer_vec.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            buffer_vec.extend_from_slice(&rdata);
+        }
+
+        buffer_vec
+    }
+
+    /// Parse a message, ignoring any bytes left over once every record counted in the header has been read.
+    /// Captures often carry padding after the DNS payload, so this is the forgiving default
+    pub fn parse(buf: &[u8]) -> Result<DnsPacket, DnsError> {
+        let (packet, _) = DnsPacket::parse_with_len(buf)?;
+        Ok(packet)
+    }
+
+    /// Best-effort parse for untrusted or damaged input. Only the header is required: each section is read until its
+    /// first malformed entry and whatever was read before that is kept, with the header counts lowered to match.
+    /// Never panics, whatever the input - the fuzz target in fuzz/ exercises exactly this
+    pub fn parse_lenient(buf: &[u8]) -> Result<DnsPacket, DnsError> {
+
+        let header = DnsHeader::parse(buf)?;
+        let mut offset = DnsHeader::DNS_HEADER_LEN;
+
+        let mut packet = DnsPacket::new();
+
+        // Once one entry fails the position of everything after it is unknown, so later sections are abandoned too
+        let mut intact = true;
+
+        for _ in 0..header.question_count {
+            match QuestionSection::parse(buf, &mut offset) {
+                Ok(question) => packet.questions.push(question),
+                Err(_) => {
+                    intact = false;
+                    break;
+                }
+            }
+        }
+        for (count, section) in [
+            (header.answer_record_count, &mut packet.answers),
+            (header.authority_record_count, &mut packet.authorities),
+            (header.additional_record_count, &mut packet.additionals),
+        ] {
+            for _ in 0..count {
+                if !intact {
+                    break;
+                }
+                match AnswerSection::parse(buf, &mut offset) {
+                    Ok(record) => section.push(record),
+                    Err(_) => intact = false,
+                }
+            }
+        }
+
+        // A damaged OPT record is dropped rather than failing the packet
+        if let Some(index) = packet.additionals.iter().position(|additional| RecordType::from(additional.resource_record.record_type) == RecordType::Opt) {
+            let opt = packet.additionals.remove(index);
+            packet.edns = OptRecord::from_record(&opt.resource_record).ok();
+        }
+
+        packet.header = header;
+        packet.header.question_count = packet.questions.len() as u16;
+        packet.header.answer_record_count = packet.answers.len() as u16;
+        packet.header.authority_record_count = packet.authorities.len() as u16;
+        packet.header.additional_record_count = (packet.additionals.len() + packet.edns.is_some() as usize) as u16;
+
+        Ok(packet)
+    }
+
+    /// Parse a message and reject it if anything follows the last record
+    pub fn parse_strict(buf: &[u8]) -> Result<DnsPacket, DnsError> {
+        let (packet, consumed) = DnsPacket::parse_with_len(buf)?;
+
+        if consumed != buf.len() {
+            return Err(DnsError::TrailingBytes(buf.len() - consumed));
+        }
+
+        Ok(packet)
+    }
+
+    /// Parse a message and also return how many bytes of `buf` it occupied.
+    /// Exactly as many entries as the header counts are read per section - a count of 0 (common for the question
+    /// section of FORMERR/SERVFAIL replies) reads nothing, so the next section starts straight after the header
+    pub fn parse_with_len(buf: &[u8]) -> Result<(DnsPacket, usize), DnsError> {
+
+        let header = DnsHeader::parse(buf)?;
+        let mut offset = DnsHeader::DNS_HEADER_LEN;
+
+        // Every entry takes some minimum space, so counts the buffer cannot possibly hold (a forged answer count of
+        // 65535 in a 40 byte datagram) are rejected before any record is read
+        let record_count = header.answer_record_count as usize + header.authority_record_count as usize + header.additional_record_count as usize;
+        let minimum_len = offset + header.question_count as usize * MIN_QUESTION_LEN + record_count * MIN_RECORD_LEN;
+        if minimum_len > buf.len() {
+            return Err(DnsError::BufferTooShort);
+        }
+
+        let mut packet = DnsPacket::new();
+
+        for _ in 0..header.question_count {
+            packet.questions.push(QuestionSection::parse(buf, &mut offset)?);
+        }
+        for _ in 0..header.answer_record_count {
+            packet.answers.push(AnswerSection::parse(buf, &mut offset)?);
+        }
+        for _ in 0..header.authority_record_count {
+            packet.authorities.push(AnswerSection::parse(buf, &mut offset)?);
+        }
+        for _ in 0..header.additional_record_count {
+            let additional = AnswerSection::parse(buf, &mut offset)?;
+
+            if RecordType::from(additional.resource_record.record_type) == RecordType::Opt {
+                packet.edns = Some(OptRecord::from_record(&additional.resource_record)?);
+            } else {
+                packet.additionals.push(additional);
+            }
+        }
+
+        packet.header = header;
+
+        Ok((packet, offset))
+    }
+
+    /// Parse the first of possibly several TCP framed messages in `buf` (each preceded by its 2 byte length) and
+    /// return how many bytes the frame took, prefix included, so the caller can step on to the next message -
+    /// a zone transfer arrives as a run of such frames
+    pub fn parse_tcp_with_len(buf: &[u8]) -> Result<(DnsPacket, usize), DnsError> {
+
+        let prefix = buf.get(..2).ok_or(DnsError::BufferTooShort)?;
+        let frame_end = 2 + u16::from_be_bytes([prefix[0], prefix[1]]) as usize;
+        let message = buf.get(2..frame_end).ok_or(DnsError::BufferTooShort)?;
+
+        let packet = DnsPacket::parse(message)?;
+
+        Ok((packet, frame_end))
+    }
+
+    /// Read one TCP framed message from a stream: the 2 byte length prefix, then exactly that many bytes, parsed.
+    /// A stream ending early is DnsError::Io with UnexpectedEof, and a read timeout is DnsError::Timeout
+    #[cfg(feature = "std")]
+    pub fn parse_from_reader<R: Read>(reader: &mut R) -> Result<DnsPacket, DnsError> {
+
+        let mut length_prefix = [0; 2];
+        reader.read_exact(&mut length_prefix)?;
+
+        let mut message = vec![0; u16::from_be_bytes(length_prefix) as usize];
+        reader.read_exact(&mut message)?;
+
+        DnsPacket::parse(&message)
+    }
+
+    /// Serialize the whole message. The header counts are taken from the section vectors, not from the header fields.
+    /// With compress_names set, a record whose owner name was already written (as a question or an earlier record's
+    /// owner) points back to it - RFC 1035 section 4.1.4
+    pub fn serialize_to_bytes(&self) -> Vec<u8> {
+
+        let mut buffer_vec = Vec::with_capacity(512);
+        self.serialize_into(&mut buffer_vec);
+
+        buffer_vec
+    }
+
+    /// serialize_to_bytes into a caller-owned buffer, which is cleared first. Reusing one buffer across
+    /// packets avoids an allocation per message in a busy server loop
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+
+        buf.clear();
+        self.header.write_to(buf);
+
+        // Overwrite the four count fields (bytes 4..12) so they always agree with what is actually written
+        buf[4..6].copy_from_slice(&(self.questions.len() as u16).to_be_bytes());
+        buf[6..8].copy_from_slice(&(self.answers.len() as u16).to_be_bytes());
+        buf[8..10].copy_from_slice(&(self.authorities.len() as u16).to_be_bytes());
+        let additional_count = self.additionals.len() + self.edns.is_some() as usize;
+        buf[10..12].copy_from_slice(&(additional_count as u16).to_be_bytes());
+
+        // Question names go into the table too, so the usual answer owned by the queried name is a pointer to offset 12
+        let mut written_names: BTreeMap<String, u16> = BTreeMap::new();
+        for question in &self.questions {
+            if let Some(wire_name) = question.current_wire_name() {
+                remember_name_offset(&question.resource_record.name, buf.len(), &mut written_names);
+                buf.extend_from_slice(wire_name);
+                question.write_fields_to(buf);
+            } else if self.compress_names {
+                write_name_compressed(&question.resource_record.name, buf, &mut written_names);
+                question.write_fields_to(buf);
+            } else {
+                question.write_to(buf);
+            }
+        }
+        for record in self.answers.iter().chain(&self.authorities).chain(&self.additionals) {
+            if self.compress_names {
+                write_name_compressed(&record.resource_record.name, buf, &mut written_names);
+                record.write_fields_to(buf);
+            } else {
+                record.write_to(buf);
+            }
+        }
+        if let Some(opt) = &self.edns {
+            opt.to_record().write_to(buf);
+        }
+    }
+
+    /// serialize_to_bytes into a caller-provided buffer such as a stack array, returning the message length.
+    /// Nothing is allocated for names without escapes, so this suits callers that must avoid the heap.
+    /// The layout matches serialize_to_bytes, except that only the first MAX_SLICE_NAMES name suffixes written in
+    /// full are remembered as compression targets. Fails with DnsError::BufferTooShort when the message does not fit,
+    /// leaving a partial message in `buf`
+    pub fn serialize_to_slice(&self, buf: &mut [u8]) -> Result<usize, DnsError> {
+
+        let mut writer = SliceWriter { buf, len: 0, names: [0; MAX_SLICE_NAMES], name_count: 0 };
+
+        // The header counts always agree with what is actually written, as in serialize_into
+        let mut header = self.header.clone();
+        header.question_count = self.questions.len() as u16;
+        header.answer_record_count = self.answers.len() as u16;
+        header.authority_record_count = self.authorities.len() as u16;
+        header.additional_record_count = (self.additionals.len() + self.edns.is_some() as usize) as u16;
+        writer.put(&header.to_bytes())?;
+
+        for question in &self.questions {
+            let record = &question.resource_record;
+            match question.current_wire_name() {
+                Some(wire_name) => {
+                    if self.compress_names {
+                        writer.remember_wire_name(wire_name);
+                    }
+                    writer.put(wire_name)?;
+                }
+                None => writer.put_name(&record.name, self.compress_names)?,
+            }
+            writer.put(&record.record_type.to_be_bytes())?;
+            writer.put(&record.class.to_be_bytes())?;
+        }
+
+        for answer in self.answers.iter().chain(&self.authorities).chain(&self.additionals) {
+            let record = &answer.resource_record;
+            writer.put_name(&record.name, self.compress_names)?;
+            writer.put(&record.record_type.to_be_bytes())?;
+            writer.put(&record.class.to_be_bytes())?;
+            writer.put(&record.ttl.to_be_bytes())?;
+            writer.put(&(record.rdata.encoded_len() as u16).to_be_bytes())?;
+            let mut result = Ok(());
+            record.rdata.visit_encoded(|bytes| {
+                if result.is_ok() {
+                    result = writer.put(bytes);
+                }
+            });
+            result?;
+        }
+
+        // The OPT record field by field, as OptRecord::to_record lays it out
+        if let Some(opt) = &self.edns {
+            let rdata_len: usize = opt.options.iter().map(|option| 4 + option.data.len()).sum();
+            writer.put(&[0])?;
+            writer.put(&u16::from(RecordType::Opt).to_be_bytes())?;
+            writer.put(&opt.udp_payload_size.to_be_bytes())?;
+            writer.put(&opt.packed_ttl().to_be_bytes())?;
+            writer.put(&(rdata_len as u16).to_be_bytes())?;
+            for option in &opt.options {
+                writer.put(&option.code.to_be_bytes())?;
+                writer.put(&(option.data.len() as u16).to_be_bytes())?;
+                writer.put(&option.data)?;
+            }
+        }
+
+        Ok(writer.len)
+    }
+
+    /// The bytes PartialEq and Hash compare: the message without id, compression or name casing
+    fn identity_bytes(&self) -> Vec<u8> {
+
+        let mut packet = self.clone();
+        packet.header.id = 0;
+        packet.compress_names = false;
+
+        for question in &mut packet.questions {
+            question.resource_record.name.make_ascii_lowercase();
+            question.wire_name = None;
+        }
+        for record in packet.answers.iter_mut().chain(&mut packet.authorities).chain(&mut packet.additionals) {
+            record.resource_record.name.make_ascii_lowercase();
+        }
+
+        packet.serialize_to_bytes()
+    }
+
+    /// Serialize for a TCP stream, where every message is preceded by its length as a big endian u16, see frame_tcp.
+    /// Fails with DnsError::MessageTooLong for a message over 65535 bytes, which no length prefix can describe
+    pub fn serialize_to_tcp_bytes(&self) -> Result<Vec<u8>, DnsError> {
+        frame_tcp(&self.serialize_to_bytes())
+    }
+
+    /// Write the message to a stream as serialize_to_tcp_bytes frames it, in a single write_all call so the
+    /// prefix and message are not split into separate TCP segments. The reverse of parse_from_reader.
+    /// A message too long to frame is an InvalidInput error, and nothing is written
+    #[cfg(feature = "std")]
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let framed = self.serialize_to_tcp_bytes().map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        writer.write_all(&framed)
+    }
+}
+
+impl Default for DnsPacket {
+    fn default() -> DnsPacket {
+        DnsPacket::new()
+    }
+}
+
+/// Two packets are the same message when they serialize to the same bytes once the transaction id is zeroed and
+/// question and owner names are lowercased, so a retry under a fresh id or with different name casing compares
+/// (and hashes) equal. Compression is left out as well, and the header counts always follow the sections
+impl PartialEq for DnsPacket {
+    fn eq(&self, other: &DnsPacket) -> bool {
+        self.identity_bytes() == other.identity_bytes()
+    }
+}
+
+impl Eq for DnsPacket {}
+
+impl Hash for DnsPacket {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.identity_bytes().hash(state);
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DnsHeader {
                                         /*   https://www.rfc-editor.org/rfc/rfc1035#section-4.1.1   */
                                         /*   https://en.wikipedia.org/wiki/Domain_Name_System#DNS_message_format   <-- The header format here is current   */ 
@@ -33,7 +693,7 @@ pub struct DnsHeader {
 }
 
 impl DnsHeader {
-    const DNS_HEADER_LEN:usize = 12;
+    pub const DNS_HEADER_LEN:usize = 12;
 
     pub fn new() -> DnsHeader {
         DnsHeader {
@@ -59,11 +719,23 @@ impl DnsHeader {
 
     /// Convert each field of the DnsHeader struct to a Big Endian byte vector
     pub fn serialize_to_bytes(&self) -> Vec<u8> {
-        
+
         let mut buffer_vec = Vec::with_capacity(DnsHeader::DNS_HEADER_LEN);
+        self.write_to(&mut buffer_vec);
+
+        buffer_vec
+    }
 
-        buffer_vec.extend_from_slice(&self.id.to_be_bytes());     // u16 to big endian bytes
-        buffer_vec.push(
+    /// serialize_to_bytes into a caller-owned buffer, which is cleared first
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+        self.write_to(buf);
+    }
+
+    /// The two packed flag bytes (QR, opcode, AA, TC, RD, then RA, Z, AD, CD, rcode) exactly as they go on the wire
+    pub fn flag_bytes(&self) -> [u8; 2] {
+
+        [
             ((self.query_indicator as u8) << 7)                   // Convert to u8 then shift the bit 7 places to the left (most significant bit) - if true: 00000001 << 7  becomes  10000000 
                 | (self.opcode << 3)                              // shift the opcode(4 bits) left 3 bits and perform bitwise OR to the query_indicator bits 
                                                                     //(ex. with opcode=1:  10000000 | (00000001 << 3) => 10000000 | 00001000 => resulting OR => 10001000) 
@@ -71,42 +743,124 @@ impl DnsHeader {
                 | ((self.authoritative_answer as u8) << 2)
                 | ((self.truncation as u8) << 1)
                 | self.recursion_desired as u8,
-        );
 
-        // The same bit wise operations and big endian convertions occur for the rest of the DnsHeader fields... 
-        buffer_vec.push(
+            // The same bit wise operations and big endian convertions occur for the rest of the DnsHeader fields... 
             ((self.recursion_available as u8) << 7)         // 00000001 <<7 => 10000000
                 | ((self.reserved as u8) << 6)              // 00000001 <<6 => 01000000 | 10000000 => 11000000
                 | ((self.authentic_data as u8) << 5)        // 00000001 <<5 => 00100000 | 11000000 => 11100000
                 | ((self.check_disabled as u8) << 4)        // 00000001 <<4 => 00010000 | 11100000 => 11110000
                 | self.response_code,                       //                                            ^^^^ the 4 bit response_code already has it's signficant bits in the lower 4 bits, so just OR 
-        );
+        ]
+    }
 
-        // Append remaining header fields
-        buffer_vec.extend_from_slice(&self.question_count.to_be_bytes());
-        buffer_vec.extend_from_slice(&self.answer_record_count.to_be_bytes());
-        buffer_vec.extend_from_slice(&self.authority_record_count.to_be_bytes());
-        buffer_vec.extend_from_slice(&self.additional_record_count.to_be_bytes());
+    /// Append the 12 header bytes to the end of the buffer
+    pub(crate) fn write_to(&self, buffer_vec: &mut Vec<u8>) {
+        buffer_vec.extend_from_slice(&self.to_bytes());
+    }
 
+    /// The 12 header bytes as a fixed size array
+    pub fn to_bytes(&self) -> [u8; DnsHeader::DNS_HEADER_LEN] {
 
-        buffer_vec
+        let mut bytes = [0; DnsHeader::DNS_HEADER_LEN];
+
+        bytes[0..2].copy_from_slice(&self.id.to_be_bytes());     // u16 to big endian bytes
+        bytes[2..4].copy_from_slice(&self.flag_bytes());
+
+        // Remaining header fields
+        bytes[4..6].copy_from_slice(&self.question_count.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.answer_record_count.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.authority_record_count.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.additional_record_count.to_be_bytes());
+
+        bytes
+    }
+
+    /// Read the 12 byte header from the start of a message - the reverse of serialize_to_bytes
+    pub fn parse(buf: &[u8]) -> Result<DnsHeader, DnsError> {
+
+        if buf.len() < DnsHeader::DNS_HEADER_LEN {
+            return Err(DnsError::BufferTooShort);
+        }
+
+        let flags_high = buf[2];
+        let flags_low = buf[3];
+
+        Ok(DnsHeader {
+            id: u16::from_be_bytes([buf[0], buf[1]]),
+
+            query_indicator: flags_high & 0b1000_0000 != 0,
+            opcode: (flags_high >> 3) & 0b0000_1111,
+            authoritative_answer: flags_high & 0b0000_0100 != 0,
+            truncation: flags_high & 0b0000_0010 != 0,
+            recursion_desired: flags_high & 0b0000_0001 != 0,
+            recursion_available: flags_low & 0b1000_0000 != 0,
+            reserved: flags_low & 0b0100_0000 != 0,
+            authentic_data: flags_low & 0b0010_0000 != 0,
+            check_disabled: flags_low & 0b0001_0000 != 0,
+            response_code: flags_low & 0b0000_1111,
+
+            question_count: u16::from_be_bytes([buf[4], buf[5]]),
+            answer_record_count: u16::from_be_bytes([buf[6], buf[7]]),
+            authority_record_count: u16::from_be_bytes([buf[8], buf[9]]),
+            additional_record_count: u16::from_be_bytes([buf[10], buf[11]]),
+        })
+    }
+}
+
+impl Default for DnsHeader {
+    fn default() -> DnsHeader {
+        DnsHeader::new()
     }
 }
 
 
 /// The question section has a simpler format than the resource record format used in the other sections. Each question record (there is usually just one in the section)
+#[derive(Debug, Clone)]
 pub struct QuestionSection {
     // The domain name is broken into discrete labels which are concatenated; each label is prefixed by the length of that label
     pub resource_record: ResourceRecord,
+    pub wire_name: Option<Vec<u8>>,     // Encoded name as parsed (or given to from_wire_name), written verbatim while it still spells resource_record.name
 }   
 
 impl QuestionSection {
     pub fn new() -> QuestionSection {
         QuestionSection { 
-            resource_record: ResourceRecord::new()
+            resource_record: ResourceRecord::new(),
+            wire_name: None,
             }
     }
+
+    /// A question for `name` of the given type and class, ready to push onto a query's question section
+    pub fn new_query(name: &str, record_type: RecordType, class: DnsClass) -> QuestionSection {
+
+        let mut question = QuestionSection::new();
+        question.resource_record.name = name.to_string();
+        question.resource_record.record_type = record_type.into();
+        question.resource_record.class = class.into();
+
+        question
+    }
     
+    /// A question whose name is given in its uncompressed wire form, as read from a query (see read_name_wire).
+    /// Those exact bytes are what gets written back out, so nothing is lost to a decode and re-encode round trip -
+    /// letter case, escaped bytes and all. resource_record.name is filled in with the decoded text for lookups.
+    /// Setting resource_record.name to a different name later makes serialization encode that name instead;
+    /// a change of letter case alone is not enough, clear wire_name for that
+    pub fn from_wire_name(wire_name: &[u8], record_type: RecordType, class: DnsClass) -> Result<QuestionSection, DnsError> {
+
+        // A lone name has nothing earlier to point at, so any compression pointer is rejected by read_name
+        let mut offset = 0;
+        let name = read_name(wire_name, &mut offset)?;
+        if offset != wire_name.len() {
+            return Err(DnsError::TrailingBytes(wire_name.len() - offset));
+        }
+
+        let mut question = QuestionSection::new_query(&name, record_type, class);
+        question.wire_name = Some(wire_name.to_vec());
+
+        Ok(question)
+    }
+
     /// Given standard URL, Separate by '.' ; Get the length of the first label; place length in hex to the front; get length of second label (TDL); replace with length in hex; append null byte.
     /// example: google.com becomes: \x06google\x03com\x00
     pub fn to_label_sequence(&self) -> String {
@@ -138,29 +892,81 @@ impl QuestionSection {
         let capacity = self.resource_record.name.len() + 32;    // Capacity is the length of the name + the 4 bytes of the record_type and record_class field 
 
         let mut buffer_vec: Vec<u8> = Vec::with_capacity(capacity);
+        self.write_to(&mut buffer_vec);
 
-        // Clone the name (which at this point should be a label) and convert it to bytes
-        let name = self.resource_record.name.clone();
-        let mut name_bytes = name.into_bytes();
+        buffer_vec
+    }
 
-        buffer_vec.append(&mut name_bytes);
-    
-        // Append remaining header fields
+    /// serialize_to_bytes into a caller-owned buffer, which is cleared first
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+        self.write_to(buf);
+    }
+
+    /// Append the encoded question to the end of the buffer
+    pub(crate) fn write_to(&self, buffer_vec: &mut Vec<u8>) {
+
+        // Encode the dotted name into its wire format label sequence, unless the encoded form is at hand
+        match self.current_wire_name() {
+            Some(wire_name) => buffer_vec.extend_from_slice(wire_name),
+            None => write_name(&self.resource_record.name, buffer_vec),
+        }
+        self.write_fields_to(buffer_vec);
+    }
+
+    /// wire_name, provided it still holds the same name as resource_record.name (ignoring case). Anything else
+    /// means the name was changed after parsing, and the stale bytes must not be written
+    pub(crate) fn current_wire_name(&self) -> Option<&[u8]> {
+        let wire_name = self.wire_name.as_deref()?;
+        name_matches(wire_name, 0, &self.resource_record.name).unwrap_or(false).then_some(wire_name)
+    }
+
+    /// Append the type and class that follow the question name
+    fn write_fields_to(&self, buffer_vec: &mut Vec<u8>) {
         buffer_vec.extend_from_slice(&self.resource_record.record_type.to_be_bytes());
         buffer_vec.extend_from_slice(&self.resource_record.class.to_be_bytes());
-
-        buffer_vec
     }
-}
 
-pub struct ResourceRecord {
+    /// Canonical "name/type/class" key for the question, lowercased and without a trailing root dot, so questions
+    /// that differ only in case share cache entries: "Example.COM. A IN" and "example.com A IN" are both "example.com/a/in"
+    pub fn cache_key(&self) -> String {
+
+        let record = &self.resource_record;
+        let name = record.name.trim_end_matches('.');
+
+        format!("{}/{}/{}", name, RecordType::from(record.record_type), DnsClass::from(record.class)).to_ascii_lowercase()
+    }
+
+    /// Read one question (name, type, class) starting at `offset`, advancing `offset` past it. The name's own bytes
+    /// are kept in wire_name, so serializing the question again reproduces it exactly however unusual its
+    /// encoding (a compressed name comes back uncompressed, with the same labels)
+    pub fn parse(buf: &[u8], offset: &mut usize) -> Result<QuestionSection, DnsError> {
+
+        let mut question = QuestionSection::new();
+
+        question.wire_name = Some(read_name_wire(buf, &mut offset.clone())?.into_owned());
+        question.resource_record.name = read_name(buf, offset)?;
+        question.resource_record.record_type = read_u16(buf, offset)?;
+        question.resource_record.class = read_u16(buf, offset)?;
+
+        Ok(question)
+    }
+}
+
+impl Default for QuestionSection {
+    fn default() -> QuestionSection {
+        QuestionSection::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResourceRecord {
                             /*   https://en.wikipedia.org/wiki/Domain_Name_System#Resource_records   */
     pub name: String,               // [Variable size] Name of the node to which this record pertains
     pub record_type: u16,           // 2 byte 	Type of resource record in numeric form (e.g., 15 for MX RRs)
     pub class: u16,                 // 2 byte   class code
     pub ttl: u32,                   // 4 byte   Count of seconds that the RR stays valid (The maximum is 231−1, which is about 68 years)
-    pub record_data_length: u16,    // 2 byte   Length of RDATA field (specified in octets)
-    pub record_data: Vec<u8>,        // [Variable size] Additonal resource record specific data
+    pub rdata: RData,               // [Variable size] Record specific data, typed so it encodes itself and measures its own length
 }
 
 impl ResourceRecord {
@@ -170,10 +976,17 @@ impl ResourceRecord {
             record_type: 1, 
             class: 1, 
             ttl: 60, 
-            record_data_length: 4, 
-            record_data: Vec::new()
+            rdata: RData::Raw { rtype: RecordType::A, bytes: Vec::new() },
         }
     }
+
+    /// The rdata split into labelled components according to record_type, e.g. MX gives
+    /// [("preference", "10"), ("exchange", "mail.example.com")]. See RData::fields. Rdata that does not fit its
+    /// type comes back whole as a single hex "data" field
+    pub fn rdata_fields(&self) -> Vec<(&'static str, String)> {
+        self.rdata.fields()
+    }
+
     /// Encode RR data to 4 byte big endian (8.8.8.8 => \x08\x08\x08\x08)
     pub fn encode(&self) -> Vec<u8>{
         let encoded_vec: Vec<u8> = Vec::new();
@@ -182,6 +995,26 @@ impl ResourceRecord {
     }
 }
 
+/// Two records are the same record when name (ignoring case), type, class and rdata match. The TTL is deliberately
+/// left out, so copies of one record fetched at different times still compare equal
+impl PartialEq for ResourceRecord {
+    fn eq(&self, other: &ResourceRecord) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+            && self.record_type == other.record_type
+            && self.class == other.class
+            && self.rdata.encode() == other.rdata.encode()
+    }
+}
+
+impl Eq for ResourceRecord {}
+
+impl Default for ResourceRecord {
+    fn default() -> ResourceRecord {
+        ResourceRecord::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AnswerSection {
     pub resource_record: ResourceRecord,
 }
@@ -192,4 +1025,1261 @@ impl AnswerSection {
             resource_record: ResourceRecord::new(),
         }
     }
-}
\ No newline at end of file
+
+    /// Build an IN class answer whose type and rdata come from typed record data
+    pub fn from_rdata(name: &str, ttl: u32, rdata: RData) -> AnswerSection {
+
+        let mut answer = AnswerSection::new();
+        let record = &mut answer.resource_record;
+
+        record.name = name.to_string();
+        record.record_type = rdata.record_type().into();
+        record.ttl = ttl;
+        record.rdata = rdata;
+
+        answer
+    }
+
+    /// Build a TXT answer for text of any length - values over 255 bytes are split across several character-strings
+    pub fn txt_record(name: &str, ttl: u32, text: &str) -> AnswerSection {
+        AnswerSection::from_rdata(name, ttl, RData::txt(text))
+    }
+
+    /// The rdata in its typed form. Raw bytes held for a type that has one are decoded here, so an error means
+    /// they do not fit the layout the type requires
+    pub fn rdata(&self) -> Result<RData, DnsError> {
+        match &self.resource_record.rdata {
+            RData::Raw { rtype, bytes } => RData::parse(*rtype, bytes),
+            rdata => Ok(rdata.clone()),
+        }
+    }
+
+    /// The address held by an A record, or None when this is not an A record or its rdata is not exactly 4 bytes
+    pub fn as_ipv4(&self) -> Option<Ipv4Addr> {
+
+        if RecordType::from(self.resource_record.record_type) != RecordType::A {
+            return None;
+        }
+
+        match self.rdata() {
+            Ok(RData::A(address)) => Some(address),
+            _ => None,
+        }
+    }
+
+    /// The address held by an AAAA record, or None when this is not an AAAA record or its rdata is not exactly 16 bytes
+    pub fn as_ipv6(&self) -> Option<Ipv6Addr> {
+
+        if RecordType::from(self.resource_record.record_type) != RecordType::Aaaa {
+            return None;
+        }
+
+        match self.rdata() {
+            Ok(RData::Aaaa(address)) => Some(address),
+            _ => None,
+        }
+    }
+
+    /// Check that the rdata fits its record type: fixed size types must have exactly their size (A is 4 bytes,
+    /// AAAA is 16) and structured types must decode with no bytes left over. Catches malformed upstream data
+    pub fn validate_rdata(&self) -> Result<(), DnsError> {
+        self.rdata().map(|_| ())
+    }
+
+    /// Convert the record to its wire format: name, type, class, ttl, rdata length and rdata.
+    /// The rdata length written is measured from the encoded rdata
+    pub fn serialize_to_bytes(&self) -> Vec<u8> {
+
+        let mut buffer_vec = Vec::with_capacity(self.resource_record.name.len() + self.resource_record.rdata.encoded_len() + 12);
+        self.write_to(&mut buffer_vec);
+
+        buffer_vec
+    }
+
+    /// serialize_to_bytes into a caller-owned buffer, which is cleared first
+    pub fn serialize_into(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+        self.write_to(buf);
+    }
+
+    /// Append the encoded record to the end of the buffer
+    pub(crate) fn write_to(&self, buffer_vec: &mut Vec<u8>) {
+
+        write_name(&self.resource_record.name, buffer_vec);
+        self.write_fields_to(buffer_vec);
+    }
+
+    /// Append everything after the owner name: type, class, ttl, rdata length and rdata
+    fn write_fields_to(&self, buffer_vec: &mut Vec<u8>) {
+
+        let record = &self.resource_record;
+
+        buffer_vec.extend_from_slice(&record.record_type.to_be_bytes());
+        buffer_vec.extend_from_slice(&record.class.to_be_bytes());
+        buffer_vec.extend_from_slice(&record.ttl.to_be_bytes());
+        buffer_vec.extend_from_slice(&(record.rdata.encoded_len() as u16).to_be_bytes());
+        record.rdata.visit_encoded(|bytes| buffer_vec.extend_from_slice(bytes));
+    }
+
+    /// Read one resource record starting at `offset`, advancing `offset` past its rdata.
+    /// Used for the answer, authority and additional sections, which all share this format.
+    /// An rdlength claiming more bytes than remain in `buf` is DnsError::BufferTooShort, never a read past the end
+    pub fn parse(buf: &[u8], offset: &mut usize) -> Result<AnswerSection, DnsError> {
+
+        let mut answer = AnswerSection::new();
+        let record = &mut answer.resource_record;
+
+        record.name = read_name(buf, offset)?;
+        record.record_type = read_u16(buf, offset)?;
+        record.class = read_u16(buf, offset)?;
+        record.ttl = read_u32(buf, offset)?;
+        let record_data_length = read_u16(buf, offset)? as usize;
+
+        // The rdlength comes straight off the wire, so the slice is bounds checked rather than trusted
+        let rdata_end = *offset + record_data_length;
+        let bytes = buf.get(*offset..rdata_end).ok_or(DnsError::BufferTooShort)?;
+
+        // Names inside the rdata may be compressed against the rest of the message, so those types are decoded
+        // while the whole message is still at hand. Anything else is decoded on its own and kept typed only when it
+        // encodes back to the same bytes; rdata that does not fit its type stays raw so it is relayed untouched
+        let record_type = RecordType::from(record.record_type);
+        let typed = if matches!(record_type, RecordType::Cname | RecordType::Ns | RecordType::Mx | RecordType::Soa) {
+            RData::parse_in_message(record_type, buf, *offset, record_data_length).ok()
+        } else {
+            RData::parse(record_type, bytes).ok().filter(|rdata| rdata.encode() == bytes)
+        };
+        record.rdata = typed.unwrap_or_else(|| RData::Raw { rtype: record_type, bytes: bytes.to_vec() });
+
+        *offset = rdata_end;
+
+        Ok(answer)
+    }
+}
+
+impl Default for AnswerSection {
+    fn default() -> AnswerSection {
+        AnswerSection::new()
+    }
+}
+
+
+/// Numeric TYPE values used in the type field of questions and resource records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+                            /*   https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-4   */
+    A,                      // 1    IPv4 host address
+    Ns,                     // 2    Authoritative name server
+    Cname,                  // 5    Canonical name for an alias
+    Soa,                    // 6    Start of a zone of authority
+    Hinfo,                  // 13   Host CPU and operating system
+    Mx,                     // 15   Mail exchange
+    Txt,                    // 16   Text strings
+    Aaaa,                   // 28   IPv6 host address
+    Naptr,                  // 35   Naming authority pointer - RFC 3403
+    Opt,                    // 41   EDNS(0) pseudo-record, see edns.rs
+    Ds,                     // 43   Delegation signer: digest of a child zone's DNSKEY - RFC 4034
+    Rrsig,                  // 46   DNSSEC signature over an RRset - RFC 4034
+    Nsec,                   // 47   DNSSEC authenticated denial of existence
+    Dnskey,                 // 48   DNSSEC public key of a zone
+    Nsec3,                  // 50   Hashed authenticated denial of existence - RFC 5155
+    Tlsa,                   // 52   TLS certificate association for DANE - RFC 6698
+    Ixfr,                   // 251  Incremental zone transfer (questions only) - RFC 1995
+    Axfr,                   // 252  Full zone transfer (questions only, over TCP) - RFC 5936
+    Any,                    // 255  Every record type at a name (questions only) - not to be confused with DnsClass::Any
+    Unknown(u16),           // Any type this crate has no special handling for
+}
+
+impl From<u16> for RecordType {
+    fn from(value: u16) -> RecordType {
+        match value {
+            1 => RecordType::A,
+            2 => RecordType::Ns,
+            5 => RecordType::Cname,
+            6 => RecordType::Soa,
+            13 => RecordType::Hinfo,
+            15 => RecordType::Mx,
+            16 => RecordType::Txt,
+            28 => RecordType::Aaaa,
+            35 => RecordType::Naptr,
+            41 => RecordType::Opt,
+            43 => RecordType::Ds,
+            46 => RecordType::Rrsig,
+            47 => RecordType::Nsec,
+            48 => RecordType::Dnskey,
+            50 => RecordType::Nsec3,
+            52 => RecordType::Tlsa,
+            251 => RecordType::Ixfr,
+            252 => RecordType::Axfr,
+            255 => RecordType::Any,
+            other => RecordType::Unknown(other),
+        }
+    }
+}
+
+impl From<RecordType> for u16 {
+    fn from(record_type: RecordType) -> u16 {
+        match record_type {
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Hinfo => 13,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Naptr => 35,
+            RecordType::Opt => 41,
+            RecordType::Ds => 43,
+            RecordType::Rrsig => 46,
+            RecordType::Nsec => 47,
+            RecordType::Dnskey => 48,
+            RecordType::Nsec3 => 50,
+            RecordType::Tlsa => 52,
+            RecordType::Ixfr => 251,
+            RecordType::Axfr => 252,
+            RecordType::Any => 255,
+            RecordType::Unknown(other) => other,
+        }
+    }
+}
+
+
+
+/// Mnemonics as written in zone files and dig output. Types without a name use the RFC 3597 form TYPE<number>
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Ns => write!(f, "NS"),
+            RecordType::Cname => write!(f, "CNAME"),
+            RecordType::Soa => write!(f, "SOA"),
+            RecordType::Hinfo => write!(f, "HINFO"),
+            RecordType::Mx => write!(f, "MX"),
+            RecordType::Txt => write!(f, "TXT"),
+            RecordType::Aaaa => write!(f, "AAAA"),
+            RecordType::Naptr => write!(f, "NAPTR"),
+            RecordType::Opt => write!(f, "OPT"),
+            RecordType::Ds => write!(f, "DS"),
+            RecordType::Rrsig => write!(f, "RRSIG"),
+            RecordType::Nsec => write!(f, "NSEC"),
+            RecordType::Dnskey => write!(f, "DNSKEY"),
+            RecordType::Nsec3 => write!(f, "NSEC3"),
+            RecordType::Tlsa => write!(f, "TLSA"),
+            RecordType::Ixfr => write!(f, "IXFR"),
+            RecordType::Axfr => write!(f, "AXFR"),
+            RecordType::Any => write!(f, "ANY"),
+            RecordType::Unknown(other) => write!(f, "TYPE{}", other),
+        }
+    }
+}
+
+/// Accepts the mnemonics from Display in any case, plus TYPE<number> for every type
+impl FromStr for RecordType {
+    type Err = DnsError;
+
+    fn from_str(text: &str) -> Result<RecordType, DnsError> {
+        match text.to_ascii_uppercase().as_str() {
+            "A" => Ok(RecordType::A),
+            "NS" => Ok(RecordType::Ns),
+            "CNAME" => Ok(RecordType::Cname),
+            "SOA" => Ok(RecordType::Soa),
+            "HINFO" => Ok(RecordType::Hinfo),
+            "MX" => Ok(RecordType::Mx),
+            "TXT" => Ok(RecordType::Txt),
+            "AAAA" => Ok(RecordType::Aaaa),
+            "NAPTR" => Ok(RecordType::Naptr),
+            "OPT" => Ok(RecordType::Opt),
+            "DS" => Ok(RecordType::Ds),
+            "RRSIG" => Ok(RecordType::Rrsig),
+            "NSEC" => Ok(RecordType::Nsec),
+            "DNSKEY" => Ok(RecordType::Dnskey),
+            "NSEC3" => Ok(RecordType::Nsec3),
+            "TLSA" => Ok(RecordType::Tlsa),
+            "IXFR" => Ok(RecordType::Ixfr),
+            "AXFR" => Ok(RecordType::Axfr),
+            "ANY" => Ok(RecordType::Any),
+            other => parse_numeric_mnemonic(other, "TYPE").map(RecordType::from).ok_or(DnsError::UnknownMnemonic(text.to_string())),
+        }
+    }
+}
+
+
+/// Numeric CLASS values used in questions and resource records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DnsClass {
+                            /*   https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-2   */
+    In,                     // 1    Internet
+    Ch,                     // 3    Chaos
+    Hs,                     // 4    Hesiod
+    None,                   // 254  No class: UPDATE messages use it to delete one record from an RRset - RFC 2136
+    Any,                    // 255  Any class (questions only)
+    Unknown(u16),
+}
+
+impl From<u16> for DnsClass {
+    fn from(value: u16) -> DnsClass {
+        match value {
+            1 => DnsClass::In,
+            3 => DnsClass::Ch,
+            4 => DnsClass::Hs,
+            254 => DnsClass::None,
+            255 => DnsClass::Any,
+            other => DnsClass::Unknown(other),
+        }
+    }
+}
+
+impl From<DnsClass> for u16 {
+    fn from(class: DnsClass) -> u16 {
+        match class {
+            DnsClass::In => 1,
+            DnsClass::Ch => 3,
+            DnsClass::Hs => 4,
+            DnsClass::None => 254,
+            DnsClass::Any => 255,
+            DnsClass::Unknown(other) => other,
+        }
+    }
+}
+
+/// Mnemonics as written in zone files and dig output. Classes without a name use the RFC 3597 form CLASS<number>
+impl fmt::Display for DnsClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsClass::In => write!(f, "IN"),
+            DnsClass::Ch => write!(f, "CH"),
+            DnsClass::Hs => write!(f, "HS"),
+            DnsClass::None => write!(f, "NONE"),
+            DnsClass::Any => write!(f, "ANY"),
+            DnsClass::Unknown(other) => write!(f, "CLASS{}", other),
+        }
+    }
+}
+
+/// Accepts the mnemonics from Display in any case, plus CLASS<number> for every class
+impl FromStr for DnsClass {
+    type Err = DnsError;
+
+    fn from_str(text: &str) -> Result<DnsClass, DnsError> {
+        match text.to_ascii_uppercase().as_str() {
+            "IN" => Ok(DnsClass::In),
+            "CH" => Ok(DnsClass::Ch),
+            "HS" => Ok(DnsClass::Hs),
+            "NONE" => Ok(DnsClass::None),
+            "ANY" => Ok(DnsClass::Any),
+            other => parse_numeric_mnemonic(other, "CLASS").map(DnsClass::from).ok_or(DnsError::UnknownMnemonic(text.to_string())),
+        }
+    }
+}
+
+/// Prefix a serialized message with its length as a big endian u16, the framing used over TCP (RFC 1035
+/// section 4.2.2). Fails with DnsError::MessageTooLong when the message is longer than 65535 bytes
+pub fn frame_tcp(message: &[u8]) -> Result<Vec<u8>, DnsError> {
+
+    let length = u16::try_from(message.len()).map_err(|_| DnsError::MessageTooLong(message.len()))?;
+
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(message);
+
+    Ok(framed)
+}
+
+/// Lay out bytes the way `xxd` does: one line per 16 bytes, holding the offset, the bytes in hex in pairs, and the
+/// printable ASCII characters (anything else shown as '.'). Much easier to line up against the wire format than a flat list
+/// example: `00000000: 1234 0100 0001 0000 0000 0000 0667 6f6f  .4...........goo`
+pub fn hexdump(bytes: &[u8]) -> String {
+
+    let mut dump = String::new();
+
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (index, byte) in chunk.iter().enumerate() {
+            if index > 0 && index % 2 == 0 {
+                hex.push(' ');
+            }
+            hex += &format!("{:02x}", byte);
+        }
+
+        let ascii: String = chunk.iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+
+        dump += &format!("{:08x}: {:<39}  {}\n", line * 16, hex, ascii);
+    }
+
+    dump
+}
+
+/// Read the number out of an RFC 3597 generic mnemonic such as TYPE99 or CLASS3
+fn parse_numeric_mnemonic(text: &str, prefix: &str) -> Option<u16> {
+    let digits = text.strip_prefix(prefix)?;
+
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    digits.parse().ok()
+}
+
+/// OPCODE values carried in bits 1-4 of the header flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+                            /*   https://www.iana.org/assignments/dns-parameters/dns-parameters.xhtml#dns-parameters-5   */
+    Query,                  // 0    Standard query
+    IQuery,                 // 1    Inverse query, obsoleted by RFC 3425
+    Status,                 // 2    Server status request
+    Notify,                 // 4    Zone change notification - RFC 1996
+    Update,                 // 5    Dynamic update - RFC 2136
+    Unknown(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Opcode {
+        match value {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            other => Opcode::Unknown(other),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> u8 {
+        match opcode {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Unknown(other) => other,
+        }
+    }
+}
+
+
+/// RCODE values carried in the low 4 bits of the header flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseCode {
+                            /*   https://www.rfc-editor.org/rfc/rfc1035#section-4.1.1   */
+    NoError,                // 0    No error condition
+    FormErr,                // 1    The server was unable to interpret the query
+    ServFail,               // 2    The server was unable to process the query
+    NxDomain,               // 3    The name referenced in the query does not exist
+    NotImp,                 // 4    The server does not support the requested kind of query
+    Refused,                // 5    The server refuses to answer for policy reasons
+    NotAuth,                // 9    The server is not authoritative for the zone named in the query - RFC 2845
+    Unknown(u8),
+}
+
+impl From<u8> for ResponseCode {
+    fn from(value: u8) -> ResponseCode {
+        match value {
+            0 => ResponseCode::NoError,
+            1 => ResponseCode::FormErr,
+            2 => ResponseCode::ServFail,
+            3 => ResponseCode::NxDomain,
+            4 => ResponseCode::NotImp,
+            5 => ResponseCode::Refused,
+            9 => ResponseCode::NotAuth,
+            other => ResponseCode::Unknown(other),
+        }
+    }
+}
+
+impl From<ResponseCode> for u8 {
+    fn from(response_code: ResponseCode) -> u8 {
+        match response_code {
+            ResponseCode::NoError => 0,
+            ResponseCode::FormErr => 1,
+            ResponseCode::ServFail => 2,
+            ResponseCode::NxDomain => 3,
+            ResponseCode::NotImp => 4,
+            ResponseCode::Refused => 5,
+            ResponseCode::NotAuth => 9,
+            ResponseCode::Unknown(other) => other,
+        }
+    }
+}
+
+
+/// Writes a message into a fixed buffer for DnsPacket::serialize_to_slice, tracking the offsets of names written
+/// in full in a fixed table instead of write_name_compressed's map
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,                         // Bytes written so far
+    names: [u16; MAX_SLICE_NAMES],      // Offsets of name suffixes that later names may point back to
+    name_count: usize,
+}
+
+impl SliceWriter<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), DnsError> {
+
+        let destination = self.buf.get_mut(self.len..self.len + bytes.len()).ok_or(DnsError::BufferTooShort)?;
+        destination.copy_from_slice(bytes);
+        self.len += bytes.len();
+
+        Ok(())
+    }
+
+    /// Note that a name suffix is about to be written in full at `offset`
+    fn remember_name(&mut self, offset: usize) {
+        if self.name_count < MAX_SLICE_NAMES && offset <= MAX_POINTER_OFFSET {
+            self.names[self.name_count] = offset as u16;
+            self.name_count += 1;
+        }
+    }
+
+    /// remember_name for every suffix of a wire form name about to be written at the current position
+    fn remember_wire_name(&mut self, wire_name: &[u8]) {
+
+        let mut offset = self.len;
+        for label in wire_labels(wire_name) {
+            self.remember_name(offset);
+            offset += 1 + label.len();
+        }
+    }
+
+    /// write_name, or with `compress` set write_name_compressed: the longest suffix matching an earlier name
+    /// becomes a pointer to it. Labels are written one at a time, checking each remaining suffix against the table
+    fn put_name(&mut self, name: &str, compress: bool) -> Result<(), DnsError> {
+
+        let mut rest = name;
+
+        loop {
+            rest = rest.trim_start_matches('.');
+            if rest.trim_end_matches('.').is_empty() {
+                return self.put(&[0]);      // Root label terminates the name
+            }
+
+            if compress {
+                let written = &self.buf[..self.len];
+                let earlier = self.names[..self.name_count].iter().find(|&&offset| name_matches(written, offset as usize, rest).unwrap_or(false));
+                if let Some(&offset) = earlier {
+                    return self.put(&(0xC000 | offset).to_be_bytes());
+                }
+
+                self.remember_name(self.len);
+            }
+
+            let (label, after) = split_first_label(rest);
+            let mut result = Ok(());
+            visit_name_labels(label, |label| {
+                if result.is_ok() {
+                    result = self.put(&[label.len() as u8]).and_then(|()| self.put(label));
+                }
+            });
+            result?;
+            rest = after;
+        }
+    }
+}
+
+/// DnsPacket::diff for one record, with `field` naming its position such as "answer[0]"
+fn diff_record(differences: &mut Vec<String>, field: &str, ours: &AnswerSection, theirs: &AnswerSection) {
+
+    let rdata_text = |answer: &AnswerSection| match answer.rdata() {
+        Ok(rdata) => rdata.to_text(),
+        Err(_) => format!("{:02x?}", answer.resource_record.rdata.encode()),
+    };
+    let (ours_record, theirs_record) = (&ours.resource_record, &theirs.resource_record);
+
+    note_difference(differences, &format!("{}.name", field), &ours_record.name, &theirs_record.name);
+    note_difference(differences, &format!("{}.type", field), RecordType::from(ours_record.record_type), RecordType::from(theirs_record.record_type));
+    note_difference(differences, &format!("{}.class", field), DnsClass::from(ours_record.class), DnsClass::from(theirs_record.class));
+    note_difference(differences, &format!("{}.ttl", field), ours_record.ttl, theirs_record.ttl);
+    note_difference(differences, &format!("{}.rdata", field), rdata_text(ours), rdata_text(theirs));
+}
+
+/// Record "<field> <ours> != <theirs>" when the two values differ. Strings are quoted so empty names show up
+fn note_difference<T: PartialEq + fmt::Debug>(differences: &mut Vec<String>, field: &str, ours: T, theirs: T) {
+    if ours != theirs {
+        differences.push(format!("{} {:?} != {:?}", field, ours, theirs));
+    }
+}
+
+/// Read a big endian u16 at `offset` and advance past it
+fn read_u16(buf: &[u8], offset: &mut usize) -> Result<u16, DnsError> {
+    let bytes = buf.get(*offset..*offset + 2).ok_or(DnsError::BufferTooShort)?;
+    *offset += 2;
+
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Read a big endian u32 at `offset` and advance past it
+fn read_u32(buf: &[u8], offset: &mut usize) -> Result<u32, DnsError> {
+    let bytes = buf.get(*offset..*offset + 4).ok_or(DnsError::BufferTooShort)?;
+    *offset += 4;
+
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Query for www.example.com A with one answer, as an upstream would send it back
+    fn sample_response() -> DnsPacket {
+        let query = DnsPacket::query_multi(0x1234, vec![("www.example.com".to_string(), RecordType::A)]);
+        DnsPacket::answer_with(&query, vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))], 300)
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored_by_parse_and_rejected_by_parse_strict() {
+        let mut wire = sample_response().serialize_to_bytes();
+        let message_len = wire.len();
+        wire.extend_from_slice(&[0; 10]);
+
+        let (packet, consumed) = DnsPacket::parse_with_len(&wire).unwrap();
+        assert_eq!(consumed, message_len);
+        assert_eq!(packet.answers.len(), 1);
+        assert!(DnsPacket::parse(&wire).is_ok());
+        assert!(matches!(DnsPacket::parse_strict(&wire), Err(DnsError::TrailingBytes(10))));
+    }
+
+    #[test]
+    fn response_to_copies_id_and_question_and_sets_qr() {
+        let mut query = DnsPacket::query_multi(0x4242, vec![("example.com".to_string(), RecordType::Mx)]);
+        query.header.reserved = true;
+
+        let response = DnsPacket::response_to(&query);
+
+        assert_eq!(response.header.id, 0x4242);
+        assert!(response.header.query_indicator);
+        assert!(response.header.recursion_desired);
+        assert!(!response.header.reserved);
+        assert_eq!(response.header.question_count, 1);
+        assert_eq!(response.questions[0].cache_key(), "example.com/mx/in");
+        assert!(response.answers.is_empty());
+    }
+
+    #[test]
+    fn servfail_without_question_parses() {
+        // id 0x1234, QR and RD, RA and SERVFAIL, every count zero
+        let wire = [0x12, 0x34, 0x81, 0x82, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        let packet = DnsPacket::parse_strict(&wire).unwrap();
+
+        assert_eq!(packet.header.id, 0x1234);
+        assert_eq!(ResponseCode::from(packet.header.response_code), ResponseCode::ServFail);
+        assert!(packet.questions.is_empty());
+    }
+
+    #[test]
+    fn mx_rdlength_is_preference_plus_exchange() {
+        let answer = AnswerSection::from_rdata("example.com", 300, RData::Mx { preference: 10, exchange: "mail.example.com".to_string() });
+        let record = &answer.resource_record;
+
+        // 2 preference bytes + 04 mail 07 example 03 com 00
+        assert_eq!(record.rdata.encoded_len(), 2 + 18);
+        assert_eq!(answer.serialize_to_bytes()[21..23], (2u16 + 18).to_be_bytes());     // After 07 example 03 com 00, type, class and ttl
+        assert_eq!(RecordType::from(record.record_type), RecordType::Mx);
+    }
+
+    #[test]
+    fn duplicate_answers_dedup_to_one() {
+        let mut response = sample_response();
+        let mut duplicate = response.answers[0].clone();
+        duplicate.resource_record.name = "WWW.Example.COM".to_string();
+        duplicate.resource_record.ttl = 60;
+        response.answers.push(duplicate);
+
+        response.dedup_answers();
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.header.answer_record_count, 1);
+        assert_eq!(response.answers[0].resource_record.ttl, 300);
+    }
+
+    #[test]
+    fn with_new_id_keeps_the_question() {
+        let query = DnsPacket::query_multi(1, vec![("example.com".to_string(), RecordType::Aaaa)]);
+
+        let retry = query.with_new_id(2);
+
+        assert_eq!(retry.header.id, 2);
+        assert_eq!(query.header.id, 1);
+        assert_eq!(retry.questions[0].cache_key(), query.questions[0].cache_key());
+        assert_eq!(retry.serialize_to_bytes()[2..], query.serialize_to_bytes()[2..]);
+    }
+
+    #[test]
+    fn record_type_mnemonics_round_trip() {
+        assert_eq!("mx".parse::<RecordType>().unwrap(), RecordType::Mx);
+        assert_eq!(RecordType::Mx.to_string(), "MX");
+
+        assert_eq!("TYPE99".parse::<RecordType>().unwrap(), RecordType::Unknown(99));
+        assert_eq!(RecordType::Unknown(99).to_string(), "TYPE99");
+
+        // The generic form of a named type still gives the named variant
+        assert_eq!("TYPE15".parse::<RecordType>().unwrap(), RecordType::Mx);
+
+        assert!("TYPE".parse::<RecordType>().is_err());
+        assert!("BOGUS".parse::<RecordType>().is_err());
+    }
+
+    #[test]
+    fn query_multi_counts_every_question() {
+        let query = DnsPacket::query_multi(7, vec![
+            ("example.com".to_string(), RecordType::A),
+            ("example.org".to_string(), RecordType::Aaaa),
+        ]);
+        assert_eq!(query.header.question_count, 2);
+
+        let parsed = DnsPacket::parse_strict(&query.serialize_to_bytes()).unwrap();
+        assert_eq!(parsed.header.question_count, 2);
+        assert_eq!(parsed.questions[1].cache_key(), "example.org/aaaa/in");
+    }
+
+    #[test]
+    fn serialize_into_matches_serialize_to_bytes() {
+        let response = sample_response();
+
+        let mut buffer_vec = vec![0xFF; 700];       // Stale contents from an earlier message must not leak through
+        response.serialize_into(&mut buffer_vec);
+
+        assert_eq!(buffer_vec, response.serialize_to_bytes());
+    }
+
+    fn record_with_rdata(record_type: RecordType, record_data: Vec<u8>) -> AnswerSection {
+        let mut answer = AnswerSection::new();
+        answer.resource_record.name = "example.com".to_string();
+        answer.resource_record.record_type = record_type.into();
+        answer.resource_record.rdata = RData::Raw { rtype: record_type, bytes: record_data };
+        answer
+    }
+
+    #[test]
+    fn address_rdata_must_have_its_exact_length() {
+        assert!(record_with_rdata(RecordType::A, vec![192, 0, 2, 1]).validate_rdata().is_ok());
+        assert!(record_with_rdata(RecordType::A, vec![192, 0, 2]).validate_rdata().is_err());
+        assert!(record_with_rdata(RecordType::A, vec![192, 0, 2, 1, 0]).validate_rdata().is_err());
+
+        assert!(record_with_rdata(RecordType::Aaaa, vec![0; 16]).validate_rdata().is_ok());
+        assert!(record_with_rdata(RecordType::Aaaa, vec![0; 4]).validate_rdata().is_err());
+    }
+
+    #[test]
+    fn compressed_name_inside_mx_rdata_is_expanded() {
+        let mut wire = vec![0x00, 0x01, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        wire.extend_from_slice(b"\x07example\x03com\x00\x00\x0f\x00\x01");
+        // Owner points at the question name, and the exchange is "mail" followed by the same pointer
+        wire.extend_from_slice(&[0xC0, 0x0C, 0x00, 0x0f, 0x00, 0x01, 0, 0, 0x0e, 0x10, 0x00, 0x09, 0x00, 0x0a]);
+        wire.extend_from_slice(b"\x04mail\xC0\x0C");
+
+        let packet = DnsPacket::parse_strict(&wire).unwrap();
+        let mx = &packet.answers[0];
+
+        assert_eq!(mx.rdata().unwrap(), RData::Mx { preference: 10, exchange: "mail.example.com".to_string() });
+        assert_eq!(mx.resource_record.rdata.encode(), b"\x00\x0a\x04mail\x07example\x03com\x00");
+    }
+
+    #[test]
+    fn uncompressed_packets_repeat_names_in_full() {
+        let mut response = sample_response();
+        let encoded_name = encode_name("www.example.com");
+
+        let compressed = response.serialize_to_bytes();
+        response.compress_names = false;
+        let uncompressed = response.serialize_to_bytes();
+
+        let occurrences = |wire: &[u8]| wire.windows(encoded_name.len()).filter(|window| *window == &encoded_name[..]).count();
+        assert_eq!(occurrences(&compressed), 1);
+        assert_eq!(occurrences(&uncompressed), 2);
+        assert_eq!(uncompressed.len(), compressed.len() + encoded_name.len() - 2);
+        assert!(!uncompressed.windows(2).any(|window| window == [0xC0, 0x0C]));
+    }
+
+    #[test]
+    fn cache_key_ignores_case_and_root_dot() {
+        let lower = QuestionSection::new_query("example.com", RecordType::A, DnsClass::In);
+        let mixed = QuestionSection::new_query("ExAmPle.COM.", RecordType::A, DnsClass::In);
+        let other_type = QuestionSection::new_query("example.com", RecordType::Aaaa, DnsClass::In);
+
+        assert_eq!(lower.cache_key(), "example.com/a/in");
+        assert_eq!(mixed.cache_key(), lower.cache_key());
+        assert_ne!(other_type.cache_key(), lower.cache_key());
+    }
+
+    #[test]
+    fn answer_owned_by_the_question_name_points_to_offset_12() {
+        let wire = sample_response().serialize_to_bytes();
+
+        // Header, then the question: 17 name bytes, type and class
+        let answer_start = DnsHeader::DNS_HEADER_LEN + 17 + 4;
+        assert_eq!(wire[answer_start..answer_start + 2], [0xC0, 0x0C]);
+    }
+
+    #[test]
+    fn parse_lenient_survives_a_pointer_loop() {
+        // One question whose name is a pointer to itself
+        let wire = [0x00, 0x01, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 0x0C, 0, 1, 0, 1];
+
+        let packet = DnsPacket::parse_lenient(&wire).unwrap();
+
+        assert!(packet.questions.is_empty());
+        assert_eq!(packet.header.question_count, 0);
+    }
+
+    #[test]
+    fn parse_lenient_keeps_records_before_truncated_rdata() {
+        let mut wire = sample_response().serialize_to_bytes();
+        // A second answer claiming 4 bytes of rdata with only 2 left
+        wire[7] = 2;
+        wire.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0]);
+
+        let packet = DnsPacket::parse_lenient(&wire).unwrap();
+
+        assert_eq!(packet.questions.len(), 1);
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(packet.header.answer_record_count, 1);
+        assert!(DnsPacket::parse(&wire).is_err());
+    }
+
+    #[test]
+    fn parse_lenient_lowers_huge_counts() {
+        let mut wire = sample_response().serialize_to_bytes();
+        wire[4..12].copy_from_slice(&[0xFF; 8]);
+
+        let packet = DnsPacket::parse_lenient(&wire).unwrap();
+
+        assert_eq!(packet.header.question_count, packet.questions.len() as u16);
+        assert_eq!(packet.header.answer_record_count, packet.answers.len() as u16);
+        assert_eq!(packet.header.authority_record_count, 0);
+        assert_eq!(packet.header.additional_record_count, 0);
+    }
+
+    #[test]
+    fn counts_the_buffer_cannot_hold_are_rejected_up_front() {
+        let mut wire = sample_response().serialize_to_bytes();
+        wire[6..8].copy_from_slice(&0xFFFFu16.to_be_bytes());
+
+        assert!(matches!(DnsPacket::parse(&wire), Err(DnsError::BufferTooShort)));
+        assert!(matches!(DnsPacket::parse(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]), Err(DnsError::BufferTooShort)));
+    }
+
+    #[test]
+    fn servfail_error_response_fields() {
+        let question = QuestionSection::new_query("example.com", RecordType::A, DnsClass::In);
+
+        let response = DnsPacket::error_response(0x9999, Some(question), ResponseCode::ServFail);
+
+        assert_eq!(response.header.id, 0x9999);
+        assert!(response.header.query_indicator);
+        assert_eq!(ResponseCode::from(response.header.response_code), ResponseCode::ServFail);
+        assert_eq!(response.header.question_count, 1);
+        assert!(response.answers.is_empty() && response.authorities.is_empty() && response.additionals.is_empty());
+
+        assert_eq!(DnsPacket::error_response(1, None, ResponseCode::FormErr).header.question_count, 0);
+    }
+
+    #[test]
+    fn flag_bytes_match_the_serialized_header() {
+        let mut header = DnsHeader::new();
+        header.query_indicator = true;
+        header.opcode = Opcode::Notify.into();
+        header.authoritative_answer = true;
+        header.recursion_desired = true;
+        header.check_disabled = true;
+        header.response_code = ResponseCode::NxDomain.into();
+
+        assert_eq!(header.flag_bytes(), [0b1010_0101, 0b0001_0011]);
+        assert_eq!(header.flag_bytes()[..], header.serialize_to_bytes()[2..4]);
+    }
+
+    #[test]
+    fn consumed_length_is_the_wire_length() {
+        let mut response = sample_response();
+        response.edns = Some(OptRecord::new());
+        let wire = response.serialize_to_bytes();
+
+        let (_, consumed) = DnsPacket::parse_with_len(&wire).unwrap();
+
+        assert_eq!(consumed, wire.len());
+    }
+
+    #[test]
+    fn as_ipv4_checks_type_and_length() {
+        assert_eq!(record_with_rdata(RecordType::A, vec![192, 0, 2, 1]).as_ipv4(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(record_with_rdata(RecordType::A, vec![192, 0, 2]).as_ipv4(), None);
+        assert_eq!(record_with_rdata(RecordType::Txt, vec![192, 0, 2, 1]).as_ipv4(), None);
+
+        let mut ipv6 = [0; 16];
+        ipv6[15] = 1;
+        assert_eq!(record_with_rdata(RecordType::Aaaa, ipv6.to_vec()).as_ipv6(), Some(Ipv6Addr::LOCALHOST));
+        assert_eq!(record_with_rdata(RecordType::Aaaa, vec![0; 4]).as_ipv6(), None);
+        assert_eq!(record_with_rdata(RecordType::A, vec![192, 0, 2, 1]).as_ipv6(), None);
+    }
+
+    #[test]
+    fn new_query_fields_and_encoded_name() {
+        let question = QuestionSection::new_query("mail.example.com", RecordType::Mx, DnsClass::In);
+
+        assert_eq!(question.resource_record.name, "mail.example.com");
+        assert_eq!(RecordType::from(question.resource_record.record_type), RecordType::Mx);
+        assert_eq!(DnsClass::from(question.resource_record.class), DnsClass::In);
+        assert_eq!(question.serialize_to_bytes(), b"\x04mail\x07example\x03com\x00\x00\x0f\x00\x01");
+    }
+
+    #[test]
+    fn answers_question_directly_via_cname_or_not_at_all() {
+        let question = QuestionSection::new_query("www.example.com", RecordType::A, DnsClass::In);
+
+        assert!(sample_response().answers_question(&question));
+
+        let mut via_cname = DnsPacket::new();
+        via_cname.answers.push(AnswerSection::from_rdata("WWW.example.com", 300, RData::Cname("web.example.net".to_string())));
+        via_cname.answers.push(AnswerSection::from_rdata("web.example.net", 300, RData::A(Ipv4Addr::new(192, 0, 2, 1))));
+        assert!(via_cname.answers_question(&question));
+
+        let mismatch = QuestionSection::new_query("www.example.com", RecordType::Aaaa, DnsClass::In);
+        assert!(!sample_response().answers_question(&mismatch));
+        assert!(!via_cname.answers_question(&QuestionSection::new_query("ftp.example.com", RecordType::A, DnsClass::In)));
+    }
+
+    #[test]
+    fn strip_dnssec_removes_signatures_and_lowers_the_counts() {
+        let mut response = sample_response();
+        response.answers.push(AnswerSection::from_rdata("www.example.com", 300, RData::Raw { rtype: RecordType::Rrsig, bytes: vec![0; 20] }));
+        response.additionals.push(AnswerSection::from_rdata("www.example.com", 300, RData::Raw { rtype: RecordType::Nsec, bytes: vec![0; 4] }));
+        response.header.answer_record_count = 2;
+        response.header.additional_record_count = 1;
+
+        response.strip_dnssec();
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(RecordType::from(response.answers[0].resource_record.record_type), RecordType::A);
+        assert!(response.additionals.is_empty());
+        assert_eq!(response.header.answer_record_count, 1);
+        assert_eq!(response.header.additional_record_count, 0);
+    }
+
+    #[test]
+    fn hexdump_lines_look_like_xxd() {
+        let wire = sample_response().serialize_to_bytes();
+
+        let dump = hexdump(&wire);
+        let first_line = dump.lines().next().unwrap();
+
+        assert_eq!(first_line, "00000000: 1234 8100 0001 0001 0000 0000 0377 7777  .4...........www");
+        assert_eq!(dump.lines().count(), wire.len().div_ceil(16));
+        assert!(dump.ends_with('\n'));
+    }
+
+    #[test]
+    fn padding_fills_up_to_a_whole_block() {
+        for block_size in [128, 468] {
+            let mut response = sample_response();
+            response.pad_to_block(block_size);
+
+            let wire = response.serialize_to_bytes();
+            assert_eq!(wire.len() % block_size, 0, "block size {}", block_size);
+            assert!(DnsPacket::parse(&wire).unwrap().edns.unwrap().has_padding());
+
+            // Padding again replaces the old option instead of adding to it
+            response.pad_to_block(block_size);
+            assert_eq!(response.serialize_to_bytes().len(), wire.len());
+        }
+    }
+
+    #[test]
+    fn min_ttl_is_the_shortest_answer_or_authority_ttl() {
+        let query = DnsPacket::query_multi(1, vec![("example.com".to_string(), RecordType::A)]);
+        let mut response = DnsPacket::response_to(&query);
+        assert_eq!(response.min_ttl(), None);
+
+        response.answers.push(AnswerSection::from_rdata("example.com", 300, RData::A(Ipv4Addr::new(192, 0, 2, 1))));
+        response.answers.push(AnswerSection::from_rdata("example.com", 60, RData::A(Ipv4Addr::new(192, 0, 2, 2))));
+        response.authorities.push(AnswerSection::from_rdata("example.com", 3600, RData::Ns("ns1.example.com".to_string())));
+        assert_eq!(response.min_ttl(), Some(60));
+
+        // Additional records do not count
+        response.additionals.push(AnswerSection::from_rdata("ns1.example.com", 5, RData::A(Ipv4Addr::new(192, 0, 2, 53))));
+        assert_eq!(response.min_ttl(), Some(60));
+    }
+
+    #[test]
+    fn notify_carries_the_opcode_and_zone_soa_question() {
+        let wire = DnsPacket::notify(0x7777, "example.com").serialize_to_bytes();
+        assert_eq!(wire[2] >> 3 & 0x0F, 4);
+
+        let notify = DnsPacket::parse(&wire).unwrap();
+        assert_eq!(Opcode::from(notify.header.opcode), Opcode::Notify);
+        assert!(notify.header.authoritative_answer);
+        assert!(!notify.header.query_indicator);
+        assert_eq!(notify.questions.len(), 1);
+        assert_eq!(notify.questions[0].resource_record.name, "example.com");
+        assert_eq!(RecordType::from(notify.questions[0].resource_record.record_type), RecordType::Soa);
+        assert_eq!(DnsClass::from(notify.questions[0].resource_record.class), DnsClass::In);
+    }
+
+    #[test]
+    fn update_sections_are_read_under_their_rfc_2136_names() {
+        // Delete www.example.com A 192.0.2.1 from example.com: one zone entry, no prerequisites, one update
+        let mut wire = vec![0x0B, 0xAD, 0x28, 0x00, 0, 1, 0, 0, 0, 1, 0, 0];
+        wire.extend(encode_name("example.com"));
+        wire.extend_from_slice(&[0, 6, 0, 1]);
+        wire.extend(encode_name("www.example.com"));
+        wire.extend_from_slice(&[0, 1, 0, 254, 0, 0, 0, 0, 0, 4, 192, 0, 2, 1]);
+
+        let update = DnsPacket::parse(&wire).unwrap();
+
+        assert!(update.is_update());
+        assert_eq!(update.update_zone().unwrap().resource_record.name, "example.com");
+        assert!(update.prerequisites().is_empty());
+        assert_eq!(update.updates().len(), 1);
+        assert_eq!(DnsClass::from(update.updates()[0].resource_record.class), DnsClass::None);
+        assert_eq!(update.updates()[0].as_ipv4(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+
+        let query = DnsPacket::query_multi(1, vec![("example.com".to_string(), RecordType::Soa)]);
+        assert!(!query.is_update());
+        assert!(query.update_zone().is_none());
+    }
+
+    #[test]
+    fn parsed_question_is_reserialized_byte_for_byte() {
+        // Mixed case, a dot and a space inside labels, a non-ASCII byte, and an unknown type and class
+        let mut wire = vec![0x61, 0x62, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        wire.extend_from_slice(b"\x03A.b\x04x y\xff\x07ExAmPlE\x00");
+        wire.extend_from_slice(&[0xFF, 0x00, 0x00, 0x07]);
+
+        let packet = DnsPacket::parse(&wire).unwrap();
+        assert_eq!(packet.serialize_to_bytes(), wire);
+
+        // And the response built from it echoes the same question
+        let response = DnsPacket::response_to(&packet).serialize_to_bytes();
+        assert_eq!(response[DnsHeader::DNS_HEADER_LEN..], wire[DnsHeader::DNS_HEADER_LEN..]);
+    }
+
+    #[test]
+    fn diff_names_the_one_field_that_changed() {
+        let ours = sample_response();
+        let mut theirs = ours.clone();
+        theirs.answers[0].resource_record.ttl = 60;
+
+        assert_eq!(ours.diff(&theirs), vec!["answer[0].ttl 300 != 60".to_string()]);
+        assert!(ours.diff(&ours.clone()).is_empty());
+    }
+
+    #[test]
+    fn serialize_to_slice_fills_a_stack_buffer() {
+        let mut response = sample_response();
+        response.answers.push(AnswerSection::from_rdata("www.example.com", 300, RData::A(Ipv4Addr::new(192, 0, 2, 2))));
+        response.compress_names = true;
+        let expected = response.serialize_to_bytes();
+
+        let mut buf = [0u8; 512];
+        let message_len = response.serialize_to_slice(&mut buf).unwrap();
+
+        assert_eq!(message_len, expected.len());
+        assert_eq!(buf[..message_len], expected[..]);
+
+        let mut too_small = [0u8; 20];
+        assert!(matches!(response.serialize_to_slice(&mut too_small), Err(DnsError::BufferTooShort)));
+    }
+
+    #[test]
+    fn parse_works_with_core_and_alloc_alone() {
+        // Runs under --no-default-features as well: nothing in here comes from std
+        const WIRE: [u8; 45] = [
+            0x12, 0x34, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0,
+            7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1,
+            0xC0, 0x0C, 0, 1, 0, 1, 0, 0, 0x01, 0x2C, 0, 4, 192, 0, 2, 1,
+        ];
+
+        let packet = DnsPacket::parse(&WIRE).unwrap();
+
+        assert_eq!(packet.header.id, 0x1234);
+        assert_eq!(packet.questions[0].resource_record.name, "example.com");
+        assert_eq!(packet.answers[0].resource_record.name, "example.com");
+        assert_eq!(packet.answers[0].as_ipv4(), Some(core::net::Ipv4Addr::new(192, 0, 2, 1)));
+
+        let mut buf = [0u8; 64];
+        let message_len = packet.serialize_to_slice(&mut buf).unwrap();
+        assert_eq!(DnsPacket::parse(&buf[..message_len]).unwrap(), packet);
+    }
+
+    #[test]
+    fn canonical_rrset_is_lowercased_sorted_and_deduplicated() {
+        let query = DnsPacket::query_multi(1, vec![("example.com".to_string(), RecordType::Mx)]);
+        let mut response = DnsPacket::response_to(&query);
+        for (preference, exchange) in [(20, "MAIL2.Example.com"), (10, "mail1.example.com"), (20, "mail2.example.COM")] {
+            response.answers.push(AnswerSection::from_rdata("EXAMPLE.com", 3600, RData::Mx { preference, exchange: exchange.to_string() }));
+        }
+
+        let mut expected = Vec::new();
+        for (preference, exchange) in [(10u16, "mail1.example.com"), (20, "mail2.example.com")] {
+            let rdata = RData::Mx { preference, exchange: exchange.to_string() }.encode();
+            expected.extend(encode_name("example.com"));
+            expected.extend_from_slice(&[0, 15, 0, 1, 0, 0, 0x0E, 0x10]);
+            expected.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            expected.extend(rdata);
+        }
+
+        assert_eq!(response.canonical_rrset_bytes("example.com", RecordType::Mx), expected);
+    }
+
+    #[test]
+    fn canonical_rrset_copies_binary_txt_unchanged() {
+        let mut record = ResourceRecord::new();
+        record.name = "example.com".to_string();
+        record.record_type = RecordType::Txt.into();
+        record.class = DnsClass::In.into();
+        record.ttl = 300;
+        record.rdata = RData::Raw { rtype: RecordType::Txt, bytes: vec![0x02, 0xFF, 0xFE] };
+        let mut response = DnsPacket::new();
+        response.answers.push(AnswerSection { resource_record: record });
+
+        let canonical = response.canonical_rrset_bytes("example.com", RecordType::Txt);
+
+        assert_eq!(canonical[canonical.len() - 5..], [0, 3, 0x02, 0xFF, 0xFE]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn packets_differing_only_in_id_and_case_hash_equal() {
+        use std::hash::{BuildHasher, RandomState};
+
+        let ours = sample_response();
+        let mut theirs = ours.with_new_id(0x9999);
+        theirs.questions[0].resource_record.name = "WWW.example.COM".to_string();
+
+        let hasher = RandomState::new();
+        assert_eq!(ours, theirs);
+        assert_eq!(hasher.hash_one(&ours), hasher.hash_one(&theirs));
+
+        theirs.answers[0].resource_record.ttl = 60;
+        assert_ne!(ours, theirs);
+        assert_ne!(hasher.hash_one(&ours), hasher.hash_one(&theirs));
+    }
+
+    #[test]
+    fn rdata_fields_label_each_component() {
+        let field_list = |name: &str, rdata: RData| AnswerSection::from_rdata(name, 300, rdata).resource_record.rdata_fields();
+
+        assert_eq!(field_list("example.com", RData::A(Ipv4Addr::new(192, 0, 2, 1))), vec![("address", "192.0.2.1".to_string())]);
+        assert_eq!(
+            field_list("example.com", RData::Mx { preference: 10, exchange: "mail.example.com".to_string() }),
+            vec![("preference", "10".to_string()), ("exchange", "mail.example.com".to_string())],
+        );
+
+        let soa = RData::Soa {
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024030701,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1_209_600,
+            minimum: 300,
+        };
+        let soa_fields = field_list("example.com", soa);
+        let names: Vec<&str> = soa_fields.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["mname", "rname", "serial", "refresh", "retry", "expire", "minimum"]);
+        assert_eq!(soa_fields[2].1, "2024030701");
+
+        // Rdata that does not fit its type comes back as hex
+        let mut short_a = ResourceRecord::new();
+        short_a.record_type = RecordType::A.into();
+        short_a.rdata = RData::Raw { rtype: RecordType::A, bytes: vec![192, 0] };
+        assert_eq!(short_a.rdata_fields(), vec![("data", "C000".to_string())]);
+    }
+
+    #[test]
+    fn rdlength_past_the_end_is_buffer_too_short() {
+        let mut wire = sample_response().serialize_to_bytes();
+        let rdlength_at = wire.len() - 6;
+        wire[rdlength_at..rdlength_at + 2].copy_from_slice(&100u16.to_be_bytes());
+
+        assert!(matches!(DnsPacket::parse(&wire), Err(DnsError::BufferTooShort)));
+    }
+
+    #[test]
+    fn answer_with_adds_each_rdata_under_the_question_name() {
+        let query = DnsPacket::query_multi(0x2468, vec![("www.example.com".to_string(), RecordType::A)]);
+        let addresses = [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)];
+
+        let response = DnsPacket::answer_with(&query, addresses.iter().map(|&address| RData::A(address)).collect(), 120);
+
+        assert_eq!(response.header.id, 0x2468);
+        assert!(response.header.query_indicator);
+        assert_eq!(response.questions.len(), 1);
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.header.answer_record_count, 2);
+        for (answer, address) in response.answers.iter().zip(addresses) {
+            assert_eq!(answer.resource_record.name, "www.example.com");
+            assert_eq!(answer.resource_record.ttl, 120);
+            assert_eq!(answer.as_ipv4(), Some(address));
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn parse_from_reader_reads_one_framed_message_at_a_time() {
+        use std::io::Cursor;
+
+        let first = sample_response();
+        let second = DnsPacket::query_multi(0x5678, vec![("example.org".to_string(), RecordType::Mx)]);
+        let first_wire = first.serialize_to_bytes();
+        let mut framed = (first_wire.len() as u16).to_be_bytes().to_vec();
+        framed.extend(first_wire);
+        framed.extend(second.serialize_to_tcp_bytes().unwrap());
+        let mut stream = Cursor::new(framed);
+
+        assert_eq!(DnsPacket::parse_from_reader(&mut stream).unwrap().header.id, 0x1234);
+        assert_eq!(DnsPacket::parse_from_reader(&mut stream).unwrap().header.id, 0x5678);
+
+        let ended = DnsPacket::parse_from_reader(&mut stream);
+        assert!(matches!(ended, Err(DnsError::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof));
+
+        // A message cut short inside its body
+        let mut cut_short = Cursor::new(vec![0, 40, 0x12, 0x34]);
+        assert!(matches!(DnsPacket::parse_from_reader(&mut cut_short), Err(DnsError::Io(_))));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn write_to_writer_frames_the_message() {
+        let response = sample_response();
+        let wire = response.serialize_to_bytes();
+
+        let mut written = Vec::new();
+        response.write_to_writer(&mut written).unwrap();
+
+        assert_eq!(written[..2], (wire.len() as u16).to_be_bytes());
+        assert_eq!(written[2..], wire[..]);
+
+        // Over 65535 bytes the length prefix would wrap, so nothing is written
+        let mut oversized = sample_response();
+        for _ in 0..300 {
+            oversized.answers.push(AnswerSection::txt_record("www.example.com", 300, &"x".repeat(255)));
+        }
+        let mut not_written = Vec::new();
+        let error = oversized.write_to_writer(&mut not_written).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(not_written.is_empty());
+        assert!(matches!(oversized.serialize_to_tcp_bytes(), Err(DnsError::MessageTooLong(length)) if length > 65535));
+    }
+
+    #[test]
+    fn parsed_records_hold_typed_rdata_and_keep_misfits_raw() {
+        let mut response = sample_response();
+        let mut binary_txt = AnswerSection::new();
+        binary_txt.resource_record.name = "example.com".to_string();
+        binary_txt.resource_record.record_type = RecordType::Txt.into();
+        binary_txt.resource_record.rdata = RData::Raw { rtype: RecordType::Txt, bytes: vec![0x02, 0xFF, 0xFE] };
+        response.answers.push(binary_txt);
+        let wire = response.serialize_to_bytes();
+
+        let parsed = DnsPacket::parse(&wire).unwrap();
+
+        assert_eq!(parsed.answers[0].resource_record.rdata, response.answers[0].rdata().unwrap());
+        assert!(!matches!(parsed.answers[0].resource_record.rdata, RData::Raw { .. }));
+        assert_eq!(parsed.answers[1].resource_record.rdata, RData::Raw { rtype: RecordType::Txt, bytes: vec![0x02, 0xFF, 0xFE] });
+        assert_eq!(parsed.serialize_to_bytes(), wire);
+    }
+}
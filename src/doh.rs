@@ -0,0 +1,177 @@
+use std::io::{Read, Write};
+
+use crate::dns::DnsPacket;
+use crate::error::DnsError;
+
+
+/// POST a query to a DNS-over-HTTPS endpoint using the wire format (RFC 8484 section 4.1) and parse the reply.
+/// The crate carries no TLS implementation so `stream` must already be connected: wrap it in a TLS client (for
+/// example rustls::StreamOwned) for https endpoints, or pass a plain TcpStream for an http endpoint on a trusted network.
+/// The request asks the server to close the connection, so the stream is used for one exchange only
+pub fn post_query<S: Read + Write>(stream: &mut S, host: &str, path: &str, query: &DnsPacket) -> Result<DnsPacket, DnsError> {
+
+    let body = query.serialize_to_bytes();
+
+    let request_head = format!(
+        "POST {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Content-Type: application/dns-message\r\n\
+         Accept: application/dns-message\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    );
+
+    let mut request = request_head.into_bytes();
+    request.extend_from_slice(&body);
+
+    stream.write_all(&request)?;
+    stream.flush()?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let message = http_body(&response)?;
+    DnsPacket::parse(&message)
+}
+
+
+/// Pull the body out of a complete HTTP/1.1 response, checking for a 200 status.
+/// Handles Content-Length, chunked transfer encoding, and bodies delimited by the connection closing
+fn http_body(response: &[u8]) -> Result<Vec<u8>, DnsError> {
+
+    let head_end = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or(DnsError::InvalidHttp)?;
+    let head = std::str::from_utf8(&response[..head_end]).map_err(|_| DnsError::InvalidHttp)?;
+    let body = &response[head_end + 4..];
+
+    let mut lines = head.split("\r\n");
+
+    // Status line: HTTP/1.1 200 OK
+    let status: u16 = lines.next()
+        .and_then(|status_line| status_line.split(' ').nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or(DnsError::InvalidHttp)?;
+    if status != 200 {
+        return Err(DnsError::HttpStatus(status));
+    }
+
+    let mut content_length = None;
+    let mut chunked = false;
+
+    for line in lines {
+        let Some((field, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+
+        if field.eq_ignore_ascii_case("content-length") {
+            content_length = Some(value.parse::<usize>().map_err(|_| DnsError::InvalidHttp)?);
+        } else if field.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked") {
+            chunked = true;
+        }
+    }
+
+    if chunked {
+        return dechunk(body);
+    }
+
+    match content_length {
+        Some(length) => body.get(..length).map(<[u8]>::to_vec).ok_or(DnsError::BufferTooShort),
+        None => Ok(body.to_vec()),
+    }
+}
+
+/// Reassemble a chunked body: each chunk is `<hex size>\r\n<data>\r\n`, ending with a zero sized chunk
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, DnsError> {
+
+    let mut joined = Vec::new();
+
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n").ok_or(DnsError::InvalidHttp)?;
+        let size_line = std::str::from_utf8(&body[..line_end]).map_err(|_| DnsError::InvalidHttp)?;
+        let size_text = size_line.split(';').next().unwrap_or("").trim();     // Drop any chunk extensions
+        let size = usize::from_str_radix(size_text, 16).map_err(|_| DnsError::InvalidHttp)?;
+
+        if size == 0 {
+            return Ok(joined);
+        }
+
+        let chunk_start = line_end + 2;
+        let chunk = body.get(chunk_start..chunk_start + size).ok_or(DnsError::BufferTooShort)?;
+        joined.extend_from_slice(chunk);
+
+        body = body.get(chunk_start + size + 2..).ok_or(DnsError::BufferTooShort)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dns::{RecordType, ResponseCode};
+    use crate::rdata::RData;
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+    use std::thread;
+
+    /// Accept one connection, check it is a wire format POST and answer its query with a canned dns-message,
+    /// sent as a chunked body
+    fn mock_doh_server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            let body = loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                if let Ok(body) = http_body_of_request(&request) {
+                    break body;
+                }
+            };
+
+            let query = DnsPacket::parse(&body).unwrap();
+            let message = DnsPacket::answer_with(&query, vec![RData::A(Ipv4Addr::new(192, 0, 2, 7))], 300).serialize_to_bytes();
+
+            let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            response.extend_from_slice(format!("{:x}\r\n", message.len()).as_bytes());
+            response.extend_from_slice(&message);
+            response.extend_from_slice(b"\r\n0\r\n\r\n");
+            stream.write_all(&response).unwrap();
+        });
+
+        address
+    }
+
+    /// The body of a complete POST request, or an error while more of it has yet to arrive
+    fn http_body_of_request(request: &[u8]) -> Result<Vec<u8>, DnsError> {
+        let head_end = request.windows(4).position(|window| window == b"\r\n\r\n").ok_or(DnsError::InvalidHttp)?;
+        let head = std::str::from_utf8(&request[..head_end]).unwrap();
+        assert!(head.starts_with("POST /dns-query HTTP/1.1\r\n"));
+        assert!(head.contains("Content-Type: application/dns-message"));
+
+        let length: usize = head.lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .and_then(|length| length.parse().ok())
+            .ok_or(DnsError::InvalidHttp)?;
+        request.get(head_end + 4..head_end + 4 + length).map(<[u8]>::to_vec).ok_or(DnsError::BufferTooShort)
+    }
+
+    #[test]
+    fn post_query_reads_the_dns_message_body() {
+        let address = mock_doh_server();
+        let mut stream = TcpStream::connect(address).unwrap();
+
+        let query = DnsPacket::query_multi(0x5151, vec![("example.com".to_string(), RecordType::A)]);
+        let response = post_query(&mut stream, "dns.example", "/dns-query", &query).unwrap();
+
+        assert_eq!(response.header.id, 0x5151);
+        assert_eq!(ResponseCode::from(response.header.response_code), ResponseCode::NoError);
+        assert_eq!(response.answers[0].rdata().unwrap(), RData::A(Ipv4Addr::new(192, 0, 2, 7)));
+    }
+
+    #[test]
+    fn non_200_status_is_an_error() {
+        assert!(matches!(http_body(b"HTTP/1.1 415 Unsupported Media Type\r\n\r\n"), Err(DnsError::HttpStatus(415))));
+    }
+}
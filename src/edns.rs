@@ -0,0 +1,255 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::dns::{AnswerSection, RecordType, ResourceRecord};
+use crate::error::DnsError;
+use crate::rdata::RData;
+
+
+/// The EDNS(0) OPT pseudo-record. It travels in the additional section but reuses the resource record fields
+/// for its own purposes, so it is pulled out into this struct when a packet is parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptRecord {
+                                    /*   https://www.rfc-editor.org/rfc/rfc6891#section-6.1.2   */
+    pub udp_payload_size: u16,          // CLASS field: largest UDP payload the sender can reassemble
+    pub extended_rcode: u8,             // TTL byte 1: upper 8 bits of the 12 bit response code
+    pub version: u8,                    // TTL byte 2: EDNS version, always 0
+    pub flags: u16,                     // TTL bytes 3-4: only the top bit (DO) is defined
+    pub options: Vec<EdnsOption>,       // RDATA: a sequence of {code, length, data} options
+}
+
+/// A single {code, length, data} option from the OPT record's rdata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+/// DNS Cookie option contents - RFC 7873. The client cookie is always 8 bytes; the server cookie (8 to 32 bytes)
+/// is absent until a server has handed one out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsCookie {
+    pub client: [u8; 8],
+    pub server: Option<Vec<u8>>,
+}
+
+impl DnsCookie {
+    pub const OPTION_CODE: u16 = 10;
+
+    /// Decode the option data, rejecting lengths RFC 7873 does not allow (8 bytes, or 16 to 40)
+    pub fn parse(data: &[u8]) -> Result<DnsCookie, DnsError> {
+
+        if data.len() != 8 && !(16..=40).contains(&data.len()) {
+            return Err(DnsError::InvalidRdata);
+        }
+
+        let client: [u8; 8] = data[..8].try_into().unwrap();
+        let server = (data.len() > 8).then(|| data[8..].to_vec());
+
+        Ok(DnsCookie { client, server })
+    }
+
+    pub fn to_option(&self) -> EdnsOption {
+
+        let mut data = self.client.to_vec();
+        data.extend_from_slice(self.server.as_deref().unwrap_or_default());
+
+        EdnsOption { code: DnsCookie::OPTION_CODE, data }
+    }
+}
+
+/// Extended DNS Error option - RFC 8914. Explains a failure beyond what the rcode can say, e.g. code 6
+/// "DNSSEC Bogus" behind a SERVFAIL. The text is optional and meant for humans
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedError {
+    pub code: u16,
+    pub text: String,
+}
+
+impl ExtendedError {
+    pub const OPTION_CODE: u16 = 15;
+
+    /// Decode the option data: a 2 byte info-code followed by UTF-8 text
+    pub fn parse(data: &[u8]) -> Result<ExtendedError, DnsError> {
+
+        let code = data.get(..2).ok_or(DnsError::InvalidRdata)?;
+
+        Ok(ExtendedError {
+            code: u16::from_be_bytes([code[0], code[1]]),
+            text: String::from_utf8_lossy(&data[2..]).into_owned(),
+        })
+    }
+
+    pub fn to_option(&self) -> EdnsOption {
+
+        let mut data = self.code.to_be_bytes().to_vec();
+        data.extend_from_slice(self.text.as_bytes());
+
+        EdnsOption { code: ExtendedError::OPTION_CODE, data }
+    }
+}
+
+impl OptRecord {
+    const DNSSEC_OK: u16 = 0x8000;      // DO bit: the sender wants RRSIG/DNSKEY/NSEC records included - RFC 3225
+    pub const PADDING_OPTION_CODE: u16 = 12;    // Padding option: zero bytes whose only job is to blur the message size - RFC 7830
+
+    pub fn new() -> OptRecord {
+        OptRecord {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        }
+    }
+
+    pub fn dnssec_ok(&self) -> bool {
+        self.flags & OptRecord::DNSSEC_OK != 0
+    }
+
+    pub fn set_dnssec_ok(&mut self, dnssec_ok: bool) {
+        if dnssec_ok {
+            self.flags |= OptRecord::DNSSEC_OK;
+        } else {
+            self.flags &= !OptRecord::DNSSEC_OK;
+        }
+    }
+
+    /// The cookie option, if present and well formed
+    pub fn cookie(&self) -> Option<DnsCookie> {
+        self.options.iter()
+            .find(|option| option.code == DnsCookie::OPTION_CODE)
+            .and_then(|option| DnsCookie::parse(&option.data).ok())
+    }
+
+    /// Attach a cookie, replacing any cookie option already present
+    pub fn set_cookie(&mut self, cookie: &DnsCookie) {
+        self.options.retain(|option| option.code != DnsCookie::OPTION_CODE);
+        self.options.push(cookie.to_option());
+    }
+
+    /// True when a padding option is present, which is how a client asks for padded responses
+    pub fn has_padding(&self) -> bool {
+        self.options.iter().any(|option| option.code == OptRecord::PADDING_OPTION_CODE)
+    }
+
+    /// Set the padding option to `length` zero bytes, replacing any padding already present. The option itself
+    /// adds a 4 byte header on top of `length`
+    pub fn set_padding(&mut self, length: usize) {
+        self.options.retain(|option| option.code != OptRecord::PADDING_OPTION_CODE);
+        self.options.push(EdnsOption { code: OptRecord::PADDING_OPTION_CODE, data: vec![0; length] });
+    }
+
+    /// The first well-formed Extended DNS Error option. A response may carry several; this is the one to report
+    pub fn extended_error(&self) -> Option<ExtendedError> {
+        self.options.iter()
+            .filter(|option| option.code == ExtendedError::OPTION_CODE)
+            .find_map(|option| ExtendedError::parse(&option.data).ok())
+    }
+
+    /// Extended rcode, version and flags as they share the OPT record's TTL field
+    pub(crate) fn packed_ttl(&self) -> u32 {
+        ((self.extended_rcode as u32) << 24) | ((self.version as u32) << 16) | self.flags as u32
+    }
+
+    /// Pack the OPT fields back into a resource record for the additional section
+    pub fn to_record(&self) -> AnswerSection {
+
+        let mut rdata = Vec::new();
+        for option in &self.options {
+            rdata.extend_from_slice(&option.code.to_be_bytes());
+            rdata.extend_from_slice(&(option.data.len() as u16).to_be_bytes());
+            rdata.extend_from_slice(&option.data);
+        }
+
+        let mut answer = AnswerSection::new();
+        let record = &mut answer.resource_record;
+
+        record.name = String::new();            // OPT is always owned by the root name
+        record.record_type = RecordType::Opt.into();
+        record.class = self.udp_payload_size;
+        record.ttl = self.packed_ttl();
+        record.rdata = RData::Raw { rtype: RecordType::Opt, bytes: rdata };
+
+        answer
+    }
+
+    /// Unpack an OPT resource record read from the additional section
+    pub fn from_record(record: &ResourceRecord) -> Result<OptRecord, DnsError> {
+
+        let mut options = Vec::new();
+        let rdata = record.rdata.encode();
+        let mut offset = 0;
+
+        while offset < rdata.len() {
+            let option_header = rdata.get(offset..offset + 4).ok_or(DnsError::BufferTooShort)?;
+            let code = u16::from_be_bytes([option_header[0], option_header[1]]);
+            let length = u16::from_be_bytes([option_header[2], option_header[3]]) as usize;
+            offset += 4;
+
+            let data = rdata.get(offset..offset + length).ok_or(DnsError::BufferTooShort)?;
+            options.push(EdnsOption { code, data: data.to_vec() });
+            offset += length;
+        }
+
+        Ok(OptRecord {
+            udp_payload_size: record.class,
+            extended_rcode: (record.ttl >> 24) as u8,
+            version: (record.ttl >> 16) as u8,
+            flags: record.ttl as u16,
+            options,
+        })
+    }
+}
+
+impl Default for OptRecord {
+    fn default() -> OptRecord {
+        OptRecord::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn dnssec_ok_sets_the_top_bit_of_the_flags_word() {
+        let mut opt = OptRecord::new();
+        opt.set_dnssec_ok(true);
+
+        // TTL bytes 3-4 on the wire: the flags word, DO first
+        let ttl = opt.to_record().resource_record.ttl.to_be_bytes();
+        assert_eq!([ttl[2], ttl[3]], [0x80, 0x00]);
+
+        opt.set_dnssec_ok(false);
+        assert_eq!(opt.to_record().resource_record.ttl, 0);
+    }
+
+    #[test]
+    fn client_cookie_round_trips() {
+        let cookie = DnsCookie { client: [1, 2, 3, 4, 5, 6, 7, 8], server: None };
+        let mut opt = OptRecord::new();
+        opt.set_cookie(&cookie);
+
+        let parsed = OptRecord::from_record(&opt.to_record().resource_record).unwrap();
+
+        assert_eq!(parsed.cookie(), Some(cookie));
+        assert_eq!(parsed.options[0].data.len(), 8);
+        assert!(DnsCookie::parse(&[0; 9]).is_err());
+    }
+
+    #[test]
+    fn extended_error_round_trips() {
+        let error = ExtendedError { code: 6, text: "DNSSEC Bogus".to_string() };
+        let mut opt = OptRecord::new();
+        opt.options.push(error.to_option());
+
+        let parsed = OptRecord::from_record(&opt.to_record().resource_record).unwrap();
+
+        assert_eq!(parsed.extended_error(), Some(error));
+        assert!(ExtendedError::parse(&[0]).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io;
+
+
+/// Everything that can go wrong while reading or writing a DNS message
+#[derive(Debug)]
+pub enum DnsError {
+    BufferTooShort,                 // Ran out of bytes while a field was still being read
+    InvalidPointer,                 // A compression pointer pointed forwards or into a loop
+    InvalidLabelType,               // A label length byte used the reserved 0b01/0b10 prefixes
+    NameTooLong,                    // A decoded name was longer than the 255 byte wire limit
+    LabelTooLong,                   // A single label was longer than 63 bytes
+    TooManyLabels,                  // A name had more than 127 labels
+    EmptyLabel,                     // A textual name had two dots in a row (or started with one)
+    InvalidEscape,                  // A textual name had a '\' escape that was cut off or out of range
+    InvalidName(String),            // A host name that fails name::is_valid_name
+    UnknownMnemonic(String),        // Text that is neither a known type/class mnemonic nor its TYPE<n>/CLASS<n> form
+    InvalidTtl(String),             // A zone file TTL that is neither plain seconds nor a 1h30m style duration
+    InvalidRdata,                   // Record data did not fit the layout its record type requires
+    TrailingBytes(usize),           // Strict parsing found this many bytes left after the last record
+    MessageTooLong(usize),          // A message of this many bytes is over the 65535 a TCP length prefix can describe
+    Timeout,                        // No usable response arrived in time
+    NoUpstreams,                    // The resolver has no upstream servers configured
+    TooManyReferrals,               // Iterative resolution followed too many referrals without reaching an answer
+    LameDelegation,                 // None of a referral's nameservers could be found or reached
+    MismatchedResponse,             // A TCP reply did not carry the id or question of the query it answered
+    TransferRefused(u8),            // A zone transfer was answered with this error rcode (REFUSED, NOTAUTH, ...)
+    InvalidTransfer,                // A zone transfer did not start with the zone's SOA record
+    NoRecords,                      // The lookup succeeded but the answer held no records of the wanted type
+    HttpStatus(u16),                // A DoH server answered with something other than 200 OK
+    InvalidHttp,                    // A DoH server's reply was not a well-formed HTTP/1.1 response
+    #[cfg(feature = "std")]
+    Io(io::Error),                  // Any other socket failure
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DnsError::BufferTooShort => write!(f, "buffer ended before the message was fully read"),
+            DnsError::InvalidPointer => write!(f, "compression pointer does not point to an earlier name"),
+            DnsError::InvalidLabelType => write!(f, "label uses a reserved length prefix"),
+            DnsError::NameTooLong => write!(f, "name is longer than 255 bytes"),
+            DnsError::LabelTooLong => write!(f, "label is longer than 63 bytes"),
+            DnsError::TooManyLabels => write!(f, "name has more than 127 labels"),
+            DnsError::EmptyLabel => write!(f, "name contains an empty label"),
+            DnsError::InvalidEscape => write!(f, "name contains a malformed escape sequence"),
+            DnsError::InvalidName(name) => write!(f, "invalid host name {:?}", name),
+            DnsError::UnknownMnemonic(text) => write!(f, "unknown mnemonic {:?}", text),
+            DnsError::InvalidTtl(text) => write!(f, "invalid TTL {:?}", text),
+            DnsError::InvalidRdata => write!(f, "record data does not match its record type"),
+            DnsError::TrailingBytes(count) => write!(f, "{} unexpected bytes after the last record", count),
+            DnsError::MessageTooLong(length) => write!(f, "{} byte message is too long to send over TCP", length),
+            DnsError::Timeout => write!(f, "timed out waiting for a response"),
+            DnsError::NoUpstreams => write!(f, "no upstream servers configured"),
+            DnsError::TooManyReferrals => write!(f, "too many referrals while resolving iteratively"),
+            DnsError::LameDelegation => write!(f, "no usable nameserver for a delegated zone"),
+            DnsError::MismatchedResponse => write!(f, "response does not match the query"),
+            DnsError::TransferRefused(rcode) => write!(f, "zone transfer failed with rcode {}", rcode),
+            DnsError::InvalidTransfer => write!(f, "zone transfer did not start with an SOA record"),
+            DnsError::NoRecords => write!(f, "no records of the requested type"),
+            DnsError::HttpStatus(status) => write!(f, "DoH server replied with HTTP status {}", status),
+            DnsError::InvalidHttp => write!(f, "malformed HTTP response from DoH server"),
+            #[cfg(feature = "std")]
+            DnsError::Io(error) => write!(f, "socket error: {}", error),
+        }
+    }
+}
+
+impl core::error::Error for DnsError {}
+
+/// Read timeouts surface as WouldBlock or TimedOut depending on the platform - both become DnsError::Timeout
+#[cfg(feature = "std")]
+impl From<io::Error> for DnsError {
+    fn from(error: io::Error) -> DnsError {
+        match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => DnsError::Timeout,
+            _ => DnsError::Io(error),
+        }
+    }
+}
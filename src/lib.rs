@@ -0,0 +1,37 @@
+/*
+*   Purpose: Library half of dns_r - the DNS wire types and the pieces built on top of them
+*   Author: Mauzy0x00
+*/
+
+// The wire format modules (dns, edns, error, name, rdata) only need core and alloc, so with the default `std`
+// feature turned off the crate builds for no_std targets that have an allocator. Everything touching sockets,
+// clocks, threads or files needs `std`
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod blocklist;
+#[cfg(feature = "std")]
+pub mod cache;
+pub mod dns;
+#[cfg(feature = "doh")]
+pub mod doh;
+pub mod edns;
+pub mod error;
+pub mod name;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+#[cfg(feature = "std")]
+pub mod ratelimit;
+pub mod rdata;
+#[cfg(feature = "std")]
+pub mod recursive;
+#[cfg(feature = "std")]
+pub mod resolver;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(all(test, feature = "std"))]
+mod test_support;
+#[cfg(feature = "std")]
+pub mod zone;
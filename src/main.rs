@@ -4,63 +4,198 @@
 *   Start Date: 04-10-2025
 */
 
-use std::net::UdpSocket;
+use std::io;
+use std::net::{Ipv4Addr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-mod dns;
-use dns::*;
+use dns_r::dns::*;
+use dns_r::rdata::RData;
+use dns_r::server::Server;
+use dns_r::zone::ZoneStore;
 
 
+const LISTEN_ADDRESS: &str = "127.0.0.1:2053";
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);    // Connections left silent this long are closed - RFC 7766 section 6.2.3
+const UDP_WORKERS: usize = 16;                                  // Datagrams answered at once; more queue up in the socket until a worker is free
+const RECV_ERROR_BACKOFF: Duration = Duration::from_millis(100);  // Pause after a transient receive error before trying again
 
-fn main() -> std::io::Result<()> {
-    
-    let socket = UdpSocket::bind("127.0.0.1:2053")?;
-    
-    // Receives a single datagram message on the socket. If the buffer is too small to hold the message it will be cut off
-    let mut recv_buffer = [0; 1024];
-    let (number_of_bytes, source_address) = socket.recv_from(&mut recv_buffer).expect("Didn't recieve data...");
 
+fn main() -> io::Result<()> {
 
-    // Create a new DNS Header
-    let mut default_response = DnsHeader::new();
+    // -v / --verbose prints a hexdump of every reply sent over UDP
+    let verbose = std::env::args().skip(1).any(|argument| argument == "-v" || argument == "--verbose");
 
-    // Hard code packet testing values
-    default_response.id = 1234;
-    default_response.query_indicator = true;
-    default_response.question_count = 1;
+    // UDP and TCP share the port; TCP is where clients go when a UDP answer comes back truncated
+    let socket = UdpSocket::bind(LISTEN_ADDRESS)?;
+    let listener = TcpListener::bind(LISTEN_ADDRESS)?;
 
-    // Setup question section
-    let domain_name = "google.com";
-    let mut question = QuestionSection::new();
-    let mut answer = AnswerSection::new();
+    // Hard code a testing record to serve: google.com A 8.8.8.8
+    let mut zone = ZoneStore::new();
+    let mut record = ResourceRecord::new();
+    record.name = "google.com".to_string();
+    record.rdata = RData::A(Ipv4Addr::new(8, 8, 8, 8));
+    zone.insert(record);
 
-    // Add the domain name to the name field and convert it to a label sequence
-    question.resource_record.name = domain_name.to_string();
-    question.resource_record.name = question.to_label_sequence();
-    question.resource_record.record_type = 1;
-    question.resource_record.class = 1;
+    let server = Arc::new(Server::new(zone));
 
-    println!("Question name label: {}", question.resource_record.name);
+    let tcp_server = Arc::clone(&server);
+    thread::spawn(move || serve_tcp_connections(listener, tcp_server));
+
+    serve_udp(socket, server, UDP_WORKERS, verbose)
+}
 
-    // Serialize the data and send to the client
-    let mut serialized_response = default_response.serialize_to_bytes();
-    serialized_response.append(&mut question.serialize_to_bytes());     // Append the QuestionSection to the response
 
-    display_sent_values(&serialized_response);
+/// Answer datagrams with a fixed pool of `workers` threads, each taking the next datagram off the shared socket.
+/// A slow upstream forward holds up one worker rather than everyone, and a flood of queries waits in the socket's
+/// receive buffer instead of spawning a thread apiece. Only returns if a worker could not be started
+fn serve_udp(socket: UdpSocket, server: Arc<Server>, workers: usize, verbose: bool) -> io::Result<()> {
 
-    socket.send_to(&serialized_response, source_address).expect("Couldn't send data");
+    let mut handles = Vec::with_capacity(workers);
+    for _ in 0..workers {
+        let socket = socket.try_clone()?;
+        let server = Arc::clone(&server);
+        handles.push(thread::spawn(move || answer_datagrams(socket, server, verbose)));
+    }
+
+    for handle in handles {
+        let _ = handle.join();
+    }
 
     Ok(())
 }
 
+/// One worker of serve_udp: receive a query, answer it, repeat. Transient receive errors (such as the ICMP port
+/// unreachable some systems report on the next receive) are logged and retried after a short pause; any other
+/// receive error ends the worker. With `verbose` set every reply is hexdumped before it is sent
+fn answer_datagrams(socket: UdpSocket, server: Arc<Server>, verbose: bool) {
+
+    loop {
+        // Receives a single datagram message on the socket. If the buffer is too small to hold the message it will be cut off
+        let mut recv_buffer = [0; 1024];
+        let (number_of_bytes, source_address) = match socket.recv_from(&mut recv_buffer) {
+            Ok(received) => received,
+            Err(error) if is_transient(&error) => {
+                println!("Couldn't receive a query: {}", error);
+                thread::sleep(RECV_ERROR_BACKOFF);
+                continue;
+            }
+            Err(error) => {
+                println!("UDP worker stopping: {}", error);
+                return;
+            }
+        };
+
+        // Malformed datagrams get a FORMERR reply, or nothing at all if not even the id could be read
+        let Some(serialized_response) = server.handle_query_from(&recv_buffer[..number_of_bytes], source_address) else {
+            continue;
+        };
+
+        if verbose {
+            print!("{}", hexdump(&serialized_response));     // Offset / hex / ASCII lines, like xxd
+        }
+
+        if let Err(error) = socket.send_to(&serialized_response, source_address) {
+            println!("Couldn't send reply to {}: {}", source_address, error);
+        }
+    }
+}
+
+/// Receive errors that say nothing about the socket itself, so receiving again may well succeed
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused
+    )
+}
+
 
-fn display_sent_values(serialized_response: &[u8]) {
+/// Accept TCP connections forever, each served on its own thread until the client closes it or goes idle
+fn serve_tcp_connections(listener: TcpListener, server: Arc<Server>) {
+
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue };
+        let server = Arc::clone(&server);
+
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|address| address.to_string()).unwrap_or_default();
+            println!("TCP connection from {}", peer);
+
+            if stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err() {
+                return;
+            }
+            if let Err(error) = server.serve_tcp(&mut stream) {
+                println!("TCP connection from {} ended: {}", peer, error);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dns_r::rdata::RData;
+    use dns_r::resolver::Resolver;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::time::Instant;
+
+    /// An upstream that takes `delay` to answer every A query with 192.0.2.1
+    fn slow_upstream(delay: Duration) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut recv_buffer = [0; 1024];
+            while let Ok((number_of_bytes, source_address)) = socket.recv_from(&mut recv_buffer) {
+                let query = DnsPacket::parse(&recv_buffer[..number_of_bytes]).unwrap();
+                thread::sleep(delay);
+                let response = DnsPacket::answer_with(&query, vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))], 300);
+                socket.send_to(&response.serialize_to_bytes(), source_address).unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[test]
+    fn slow_query_does_not_hold_up_a_fast_one() {
+        let mut zone = ZoneStore::new();
+        let mut record = ResourceRecord::new();
+        record.name = "fast.example.com".to_string();
+        record.rdata = RData::A(Ipv4Addr::new(192, 0, 2, 2));
+        zone.insert(record);
+
+        let mut server = Server::new(zone);
+        server.forwarder = Some(Resolver::new(slow_upstream(Duration::from_millis(800))));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_address = socket.local_addr().unwrap();
+        thread::spawn(move || serve_udp(socket, Arc::new(server), 2, false));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let started = Instant::now();
+        for (id, name) in [(1, "slow.example.org"), (2, "fast.example.com")] {
+            let query = DnsPacket::query_multi(id, vec![(name.to_string(), RecordType::A)]);
+            client.send_to(&query.serialize_to_bytes(), server_address).unwrap();
+        }
+
+        let mut recv_buffer = [0; 1024];
+        let number_of_bytes = client.recv(&mut recv_buffer).unwrap();
+        assert_eq!(DnsPacket::parse(&recv_buffer[..number_of_bytes]).unwrap().header.id, 2);
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        let number_of_bytes = client.recv(&mut recv_buffer).unwrap();
+        assert_eq!(DnsPacket::parse(&recv_buffer[..number_of_bytes]).unwrap().header.id, 1);
+    }
 
-    let mut binary_string = String::new();
-    for byte in serialized_response {
-        binary_string += &format!("{byte:0>8b} ");
+    #[test]
+    fn only_transient_receive_errors_are_retried() {
+        assert!(is_transient(&io::Error::from(io::ErrorKind::ConnectionReset)));
+        assert!(is_transient(&io::Error::from(io::ErrorKind::Interrupted)));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::InvalidInput)));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::PermissionDenied)));
     }
-    
-    println!("Sending: {}", binary_string);             // Display serialized data as a binary string
-    println!("Sending: {:X?}", serialized_response);    // Display serialized data as hex bytes
-    println!("Sending: {:?}", serialized_response);     // Display serialized data as integers
 }
@@ -0,0 +1,504 @@
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::error::DnsError;
+
+
+const MAX_NAME_LEN: usize = 255;        // Maximum length of an encoded name, including the length bytes and the root label
+pub(crate) const MAX_LABEL_LEN: usize = 63;     // Label lengths live in the low 6 bits of the length byte
+const MAX_LABELS: usize = 127;          // Most labels a name can hold within 255 bytes (127 one byte labels plus the root)
+pub(crate) const MAX_POINTER_OFFSET: usize = 0x3FFF;   // Compression pointers hold a 14 bit offset
+const MAX_PRESENTATION_LEN: usize = 253;    // Longest dotted name (without the trailing dot) that fits MAX_NAME_LEN
+
+
+/// Read a possibly compressed name starting at `offset`, leaving `offset` just past the name as it appears in place
+/// (after the first pointer, if the name was compressed). Labels are joined with '.', without a trailing root dot,
+/// and any byte that would be ambiguous in text is written as a zone file escape (see escape_label)
+pub fn read_name(buf: &[u8], offset: &mut usize) -> Result<String, DnsError> {
+
+    let mut labels: Vec<String> = Vec::new();
+    let (end, _) = walk_labels(buf, *offset, |label| labels.push(escape_label(label)))?;
+    *offset = end;
+
+    Ok(labels.join("."))
+}
+
+/// Read a name in its uncompressed wire form (length-prefixed labels ending with the root label) without turning it
+/// into text. A name stored in place is borrowed straight from `buf`; only a compressed name, whose labels are
+/// scattered through the message, has to be reassembled into an owned buffer
+pub fn read_name_wire<'a>(buf: &'a [u8], offset: &mut usize) -> Result<Cow<'a, [u8]>, DnsError> {
+
+    let start = *offset;
+    let mut reassembled: Vec<u8> = Vec::new();
+
+    let (end, jumped) = walk_labels(buf, start, |label| {
+        reassembled.push(label.len() as u8);
+        reassembled.extend_from_slice(label);
+    })?;
+    *offset = end;
+
+    if jumped {
+        reassembled.push(0);
+        Ok(Cow::Owned(reassembled))
+    } else {
+        Ok(Cow::Borrowed(&buf[start..end]))
+    }
+}
+
+/// Compare the possibly compressed name at `offset` with a dotted `target`, ignoring ASCII case and a trailing root
+/// dot, without building a String. Meant for filters that only need a yes or no per name. The name in `buf` is
+/// still checked as thoroughly as read_name would, so a malformed name is an error rather than a mismatch
+pub fn name_matches(buf: &[u8], offset: usize, target: &str) -> Result<bool, DnsError> {
+
+    // Only a target with escapes needs decoding into owned labels; plain ones are split in place
+    let decoded = if target.contains('\\') { Some(parse_escaped_name(target)?) } else { None };
+    let plain_labels = decoded.is_none().then(|| target.split('.').filter(|label| !label.is_empty()).map(str::as_bytes));
+    let mut expected = plain_labels.into_iter().flatten().chain(decoded.iter().flatten().map(Vec::as_slice));
+
+    let mut matches = true;
+    walk_labels(buf, offset, |label| {
+        matches &= expected.next().is_some_and(|expected_label| expected_label.eq_ignore_ascii_case(label));
+    })?;
+
+    Ok(matches && expected.next().is_none())
+}
+
+/// Iterate over the labels of an uncompressed wire form name, such as one returned by read_name_wire
+pub fn wire_labels(wire: &[u8]) -> WireLabels<'_> {
+    WireLabels { wire, position: 0 }
+}
+
+/// Label slices borrowed from a wire form name. Stops at the root label, or early if the name is cut short
+pub struct WireLabels<'a> {
+    wire: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Iterator for WireLabels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let length = *self.wire.get(self.position)? as usize;
+        if length == 0 || length > MAX_LABEL_LEN {
+            return None;
+        }
+
+        let label = self.wire.get(self.position + 1..self.position + 1 + length)?;
+        self.position += 1 + length;
+
+        Some(label)
+    }
+}
+
+/// Follow a possibly compressed name from `start`, handing each label to `visit` as a slice of `buf`.
+/// Returns the offset just past the name as it appears in place and whether a compression pointer was followed.
+/// All of the name limits (pointer direction, label count, total length) are enforced here
+fn walk_labels<'a>(buf: &'a [u8], start: usize, mut visit: impl FnMut(&'a [u8])) -> Result<(usize, bool), DnsError> {
+
+    let mut position = start;
+    let mut end = start;
+    let mut segment_start = start;      // Every pointer must jump before the start of the segment it was found in, so pointer chains always terminate
+    let mut jumped = false;
+    let mut label_count = 0;
+    let mut wire_len = 1;               // Account for the root label up front
+
+    loop {
+        let length = *buf.get(position).ok_or(DnsError::BufferTooShort)? as usize;
+
+        match length & 0xC0 {
+            0xC0 => {
+                // Compression pointer: 2 bytes, the low 14 bits are an offset from the start of the message
+                let low_byte = *buf.get(position + 1).ok_or(DnsError::BufferTooShort)? as usize;
+                let pointer = ((length & 0x3F) << 8) | low_byte;
+
+                if pointer >= segment_start {
+                    return Err(DnsError::InvalidPointer);
+                }
+                if !jumped {
+                    end = position + 2;
+                    jumped = true;
+                }
+
+                position = pointer;
+                segment_start = pointer;
+            }
+            0x00 => {
+                position += 1;
+
+                if length == 0 {
+                    break;
+                }
+
+                if label_count == MAX_LABELS {
+                    return Err(DnsError::TooManyLabels);
+                }
+
+                let label = buf.get(position..position + length).ok_or(DnsError::BufferTooShort)?;
+                wire_len += length + 1;
+                if wire_len > MAX_NAME_LEN {
+                    return Err(DnsError::NameTooLong);
+                }
+
+                visit(label);
+                label_count += 1;
+                position += length;
+            }
+            _ => return Err(DnsError::InvalidLabelType),
+        }
+    }
+
+    if !jumped {
+        end = position;
+    }
+
+    Ok((end, jumped))
+}
+
+/// Encode a dotted name as a wire format label sequence (google.com becomes 06 g o o g l e 03 c o m 00).
+/// Zone file escapes are decoded; a malformed escape is kept as literal text and labels longer than 63 bytes
+/// cannot be represented so they are cut to 63 bytes. Use parse_escaped_name to reject such names instead
+pub fn encode_name(name: &str) -> Vec<u8> {
+
+    let mut encoded = Vec::with_capacity(name.len() + 2);
+    write_name(name, &mut encoded);
+
+    encoded
+}
+
+/// encode_name, appending to an existing buffer instead of allocating a new one
+pub fn write_name(name: &str, buf: &mut Vec<u8>) {
+
+    visit_name_labels(name, |label| {
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label);
+    });
+
+    buf.push(0);    // Root label terminates the name
+}
+
+/// Hand each label of a dotted name to `visit` as the raw bytes encode_name would write for it: escapes
+/// decoded and anything past 63 bytes cut off. The root label is left to the caller
+pub(crate) fn visit_name_labels(name: &str, mut visit: impl FnMut(&[u8])) {
+
+    // Names without escapes (nearly all of them) are split in place without building a label list
+    if !name.contains('\\') {
+        for label in name.split('.').filter(|label| !label.is_empty()) {
+            visit(&label.as_bytes()[..label.len().min(MAX_LABEL_LEN)]);
+        }
+    } else {
+        for label in decode_labels(name, false).unwrap_or_default() {
+            visit(&label[..label.len().min(MAX_LABEL_LEN)]);
+        }
+    }
+}
+
+/// encode_name, also returning where each suffix of the name starts within the encoding:
+/// www.example.com gives [("www.example.com", 0), ("example.com", 4), ("com", 12)]. The suffixes are lowercased
+/// and without a trailing dot, the form write_name_compressed keys its table of written names by.
+/// The root name has no suffixes
+pub fn encode_name_with_offsets(name: &str) -> (Vec<u8>, Vec<(String, usize)>) {
+
+    let mut encoded = Vec::with_capacity(name.len() + 2);
+    let mut offsets = Vec::new();
+    let mut rest = name;
+
+    loop {
+        rest = rest.trim_start_matches('.');
+        let suffix = rest.trim_end_matches('.');
+        if suffix.is_empty() {
+            break;
+        }
+        offsets.push((suffix.to_ascii_lowercase(), encoded.len()));
+
+        let (label, after) = split_first_label(rest);
+        visit_name_labels(label, |label| {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label);
+        });
+        rest = after;
+    }
+
+    encoded.push(0);    // Root label terminates the name
+
+    (encoded, offsets)
+}
+
+/// Split a dotted name after its first label, leaving dots escaped with '\' inside the label:
+/// `a\.b.example.com` gives ("a\.b", "example.com"). The rest is empty after the last label
+pub(crate) fn split_first_label(name: &str) -> (&str, &str) {
+
+    let bytes = name.as_bytes();
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\\' => index += 2,
+            b'.' => return (&name[..index], &name[index + 1..]),
+            _ => index += 1,
+        }
+    }
+
+    (name, "")
+}
+
+/// write_name for a name inside a message being built in `buf`. The longest suffix of the name (ignoring case)
+/// already written at an offset recorded in `written` is replaced by a 2 byte compression pointer to it, so
+/// mail.example.com after www.example.com costs its first label and a pointer (RFC 1035 section 4.1.4).
+/// Every suffix written in full is remembered for later names. Offsets are from the start of `buf`
+pub(crate) fn write_name_compressed(name: &str, buf: &mut Vec<u8>, written: &mut BTreeMap<String, u16>) {
+
+    let start = buf.len();
+    let (encoded, suffixes) = encode_name_with_offsets(name);
+
+    let earlier = suffixes.iter().enumerate().find_map(|(index, (suffix, offset))| {
+        written.get(suffix).map(|&pointer| (index, *offset, pointer))
+    });
+
+    match earlier {
+        Some((index, offset, pointer)) => {
+            remember_suffixes(&suffixes[..index], start, written);
+            buf.extend_from_slice(&encoded[..offset]);
+            buf.extend_from_slice(&(0xC000 | pointer).to_be_bytes());
+        }
+        None => {
+            remember_suffixes(&suffixes, start, written);
+            buf.extend_from_slice(&encoded);
+        }
+    }
+}
+
+/// Record that `name` is about to be written in full at `offset`, so later write_name_compressed calls can point
+/// to it or to any of its suffixes
+pub(crate) fn remember_name_offset(name: &str, offset: usize, written: &mut BTreeMap<String, u16>) {
+    remember_suffixes(&encode_name_with_offsets(name).1, offset, written);
+}
+
+fn remember_suffixes(suffixes: &[(String, usize)], start: usize, written: &mut BTreeMap<String, u16>) {
+
+    for (suffix, offset) in suffixes {
+        // Pointers only have 14 bits for the offset, so names further into the message cannot be pointed at
+        if start + offset <= MAX_POINTER_OFFSET {
+            written.entry(suffix.clone()).or_insert((start + offset) as u16);
+        }
+    }
+}
+
+/// Check a host name before querying for it: at most 253 characters (ignoring one trailing dot), every label
+/// 1 to 63 characters of letters, digits, '-' and '_', with no empty labels in between. A leading "*" label is
+/// accepted for wildcards and "." on its own is the root. Escaped names are rejected - parse_escaped_name is the
+/// check for those
+pub fn is_valid_name(name: &str) -> bool {
+
+    if name == "." {
+        return true;
+    }
+
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_PRESENTATION_LEN {
+        return false;
+    }
+
+    name.split('.').enumerate().all(|(index, label)| {
+        (index == 0 && label == "*")
+            || ((1..=MAX_LABEL_LEN).contains(&label.len())
+                && label.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'))
+    })
+}
+
+/// Split a zone file style name into its raw label bytes, decoding backslash escapes on the way:
+/// `\X` is the literal character X (so `\.` is a dot inside a label) and `\DDD` is the byte with decimal value DDD.
+/// example: a\.b.example.com becomes ["a.b", "example", "com"] and my\032host.com becomes ["my host", "com"]
+pub fn parse_escaped_name(text: &str) -> Result<Vec<Vec<u8>>, DnsError> {
+    decode_labels(text, true)
+}
+
+/// Shared by parse_escaped_name (strict) and encode_name (lenient, never fails)
+fn decode_labels(text: &str, strict: bool) -> Result<Vec<Vec<u8>>, DnsError> {
+
+    let bytes = text.as_bytes();
+    let mut labels: Vec<Vec<u8>> = Vec::new();
+    let mut current: Vec<u8> = Vec::new();
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\\' => {
+                let digits = bytes.get(index + 1..index + 4).filter(|digits| digits.iter().all(u8::is_ascii_digit));
+
+                if let Some(digits) = digits {
+                    // \DDD - three decimal digits naming a single byte
+                    let value = digits.iter().fold(0u16, |value, digit| value * 10 + (digit - b'0') as u16);
+                    if value > 255 {
+                        if strict {
+                            return Err(DnsError::InvalidEscape);
+                        }
+                        current.push(b'\\');
+                        index += 1;
+                        continue;
+                    }
+                    current.push(value as u8);
+                    index += 4;
+                } else if let Some(&escaped) = bytes.get(index + 1).filter(|escaped| !escaped.is_ascii_digit()) {
+                    // \X - the next character taken literally
+                    current.push(escaped);
+                    index += 2;
+                } else {
+                    if strict {
+                        return Err(DnsError::InvalidEscape);
+                    }
+                    current.push(b'\\');
+                    index += 1;
+                }
+            }
+            b'.' => {
+                if current.is_empty() {
+                    // An empty label is only allowed as the final root label (a trailing dot, or "." on its own)
+                    let is_root = index + 1 == bytes.len();
+                    if strict && !is_root {
+                        return Err(DnsError::EmptyLabel);
+                    }
+                } else {
+                    labels.push(core::mem::take(&mut current));
+                    if strict && labels.len() > MAX_LABELS {
+                        return Err(DnsError::TooManyLabels);
+                    }
+                }
+                index += 1;
+            }
+            other => {
+                current.push(other);
+                index += 1;
+            }
+        }
+
+        if strict && current.len() > MAX_LABEL_LEN {
+            return Err(DnsError::LabelTooLong);
+        }
+    }
+
+    if !current.is_empty() {
+        labels.push(current);
+    }
+
+    if strict && labels.len() > MAX_LABELS {
+        return Err(DnsError::TooManyLabels);
+    }
+
+    if strict && labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1 > MAX_NAME_LEN {
+        return Err(DnsError::NameTooLong);
+    }
+
+    Ok(labels)
+}
+
+/// Write a raw label as zone file text: '.' and '\' are backslash escaped and anything outside printable ASCII
+/// becomes \DDD, so that parse_escaped_name turns the text back into exactly the same bytes
+fn escape_label(label: &[u8]) -> String {
+
+    let mut escaped = String::with_capacity(label.len());
+
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            0x21..=0x7E => escaped.push(byte as char),
+            _ => escaped += &format!("\\{:03}", byte),
+        }
+    }
+
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn escaped_dot_stays_inside_its_label() {
+        let labels = parse_escaped_name(r"a\.b.example.com").unwrap();
+        assert_eq!(labels, vec![b"a.b".to_vec(), b"example".to_vec(), b"com".to_vec()]);
+
+        let encoded = encode_name(r"a\.b.example.com");
+        assert_eq!(&encoded[..4], b"\x03a.b");
+
+        let mut offset = 0;
+        assert_eq!(read_name(&encoded, &mut offset).unwrap(), r"a\.b.example.com");
+    }
+
+    #[test]
+    fn names_over_the_label_cap_are_rejected() {
+        let text = ["a"; MAX_LABELS + 1].join(".");
+        assert!(matches!(parse_escaped_name(&text), Err(DnsError::TooManyLabels)));
+        assert!(parse_escaped_name(&["a"; MAX_LABELS].join(".")).is_ok());
+
+        let mut wire: Vec<u8> = [1, b'a'].repeat(MAX_LABELS + 1);
+        wire.push(0);
+        assert!(matches!(read_name(&wire, &mut 0), Err(DnsError::TooManyLabels)));
+    }
+
+    #[test]
+    fn read_name_wire_borrows_unless_compressed() {
+        // example.com at 0, then www followed by a pointer back to it at 13
+        let mut message = encode_name("example.com");
+        message.extend_from_slice(b"\x03www\xC0\x00");
+
+        let mut offset = 0;
+        let in_place = read_name_wire(&message, &mut offset).unwrap();
+        assert!(matches!(in_place, Cow::Borrowed(_)));
+        assert_eq!(offset, 13);
+
+        let compressed = read_name_wire(&message, &mut offset).unwrap();
+        assert!(matches!(compressed, Cow::Owned(_)));
+        assert_eq!(&compressed[..], &encode_name("www.example.com")[..]);
+        assert_eq!(offset, message.len());
+    }
+
+    #[test]
+    fn name_matches_follows_compression_pointers() {
+        // example.com at 0, then www followed by a pointer back to it at 13
+        let mut message = encode_name("example.com");
+        message.extend_from_slice(b"\x03www\xC0\x00");
+
+        assert!(name_matches(&message, 13, "www.example.com").unwrap());
+        assert!(name_matches(&message, 13, "WWW.Example.COM.").unwrap());
+        assert!(!name_matches(&message, 13, "www.example.org").unwrap());
+        assert!(!name_matches(&message, 13, "example.com").unwrap());
+        assert!(!name_matches(&message, 13, "mail.www.example.com").unwrap());
+
+        // A pointer to itself is malformed, not merely a mismatch
+        assert!(name_matches(b"\xC0\x00", 0, "example.com").is_err());
+    }
+
+    #[test]
+    fn is_valid_name_checks_lengths_and_labels() {
+        for valid in ["example.com", "www.example.com.", "_sip._tcp.example.com", "*.example.com", ".", "xn--bcher-kva.example"] {
+            assert!(is_valid_name(valid), "{:?} should be valid", valid);
+        }
+
+        let overlong_label = format!("{}.com", "a".repeat(MAX_LABEL_LEN + 1));
+        let overlong_name = ["abcdefghi"; 26].join(".");
+        assert_eq!(overlong_name.len(), 259);
+        for invalid in [overlong_label.as_str(), overlong_name.as_str(), "www..example.com", ".example.com", "", "www.*.example.com", "my host.com"] {
+            assert!(!is_valid_name(invalid), "{:?} should be invalid", invalid);
+        }
+    }
+
+    #[test]
+    fn encode_name_with_offsets_records_each_suffix() {
+        let (encoded, offsets) = encode_name_with_offsets("Example.COM.");
+
+        assert_eq!(encoded, b"\x07Example\x03COM\x00");
+        assert_eq!(offsets, vec![(String::from("example.com"), 0), (String::from("com"), 8)]);
+
+        let (root, no_suffixes) = encode_name_with_offsets(".");
+        assert_eq!(root, vec![0]);
+        assert!(no_suffixes.is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+
+/// Run blocking work (socket I/O, an upstream exchange) on a helper thread and await its result.
+/// The returned future is woken when the work finishes, so any executor can drive it - tokio, async-std or a
+/// hand-rolled block_on - without one of its own threads ever blocking on the socket. The work starts straight
+/// away, not on the first poll, and still runs to completion if the future is dropped
+pub fn unblock<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> Unblock<T> {
+
+    let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+    let worker_shared = Arc::clone(&shared);
+
+    thread::spawn(move || {
+        let result = work();
+
+        let mut shared = worker_shared.lock().expect("unblock lock poisoned");
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+
+    Unblock { shared }
+}
+
+
+/// Future returned by unblock, resolving to whatever the work returned
+pub struct Unblock<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+struct Shared<T> {
+    result: Option<T>,              // Set once by the helper thread when the work is done
+    waker: Option<Waker>,           // Waker of the latest poll, woken when the result arrives
+}
+
+impl<T> Future for Unblock<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<T> {
+
+        let mut shared = self.shared.lock().expect("unblock lock poisoned");
+
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::block_on;
+    use std::time::Duration;
+
+    #[test]
+    fn result_arrives_after_the_work_finishes() {
+        let slow = unblock(|| {
+            thread::sleep(Duration::from_millis(50));
+            7
+        });
+
+        assert_eq!(block_on(slow), 7);
+    }
+}
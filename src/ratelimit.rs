@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Instant;
+
+
+const MAX_TRACKED_KEYS: usize = 65_536;     // Past this many buckets, idle ones are forgotten so a flood of spoofed sources can't grow the map forever
+
+
+/// Token bucket rate limiter, one bucket per key (a source address, say). Each bucket holds up to `burst`
+/// tokens and refills at `per_second` tokens a second; every allowed event spends one token
+pub struct RateLimiter<K> {
+    pub per_second: u32,
+    pub burst: u32,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(per_second: u32, burst: u32) -> RateLimiter<K> {
+        RateLimiter {
+            per_second,
+            burst,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Spend a token from `key`'s bucket. False means the key is over its rate and the event should be limited
+    pub fn allow(&self, key: K) -> bool {
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().expect("rate limiter lock poisoned");
+
+        if buckets.len() >= MAX_TRACKED_KEYS {
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.burst as f64);
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket { tokens: self.burst as f64, refilled_at: now });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.refilled_at = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Tokens the bucket holds at `now`, counting what has trickled in since it was last refilled
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        (bucket.tokens + elapsed * self.per_second as f64).min(self.burst as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_spent_per_key() {
+        let limiter = RateLimiter::new(1, 3);
+
+        assert!((0..3).all(|_| limiter.allow("a")));
+        assert!(!limiter.allow("a"));
+        assert!(limiter.allow("b"));
+    }
+}
@@ -0,0 +1,592 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, Ipv6Addr};
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dns::RecordType;
+use crate::error::DnsError;
+use crate::name::{read_name, visit_name_labels, MAX_LABEL_LEN};
+
+
+const MAX_CHARACTER_STRING_LEN: usize = 255;    // A <character-string>'s length is a single byte
+
+
+/// Typed record data, so an answer knows how to encode itself instead of callers hand-packing bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RData {
+                                /*   https://www.rfc-editor.org/rfc/rfc1035#section-3.3   */
+    A(Ipv4Addr),                                // 4 byte address
+    Aaaa(Ipv6Addr),                             // 16 byte address - RFC 3596
+    Cname(String),                              // Canonical name the owner is an alias for
+    Mx { preference: u16, exchange: String },   // Lower preference is tried first
+    Txt(Vec<String>),                           // One or more <character-string>s, each at most 255 bytes
+    Ns(String),                                 // Host that is authoritative for the owner's zone
+    Soa {
+        mname: String,                          // Primary name server for the zone
+        rname: String,                          // Mailbox of the person responsible, with the @ written as a '.'
+        serial: u32,                            // Version number of the zone, bumped on every change
+        refresh: u32,                           // Seconds before a secondary should check for a new serial
+        retry: u32,                             // Seconds before a secondary retries a failed refresh
+        expire: u32,                            // Seconds after which a secondary stops answering without a refresh
+        minimum: u32,                           // TTL for negative answers - RFC 2308
+    },
+    Hinfo { cpu: String, os: String },          // Two character-strings describing the host
+    Naptr {
+        order: u16,                             // Records are processed lowest order first
+        preference: u16,                        // Tie breaker between records of equal order
+        flags: String,                          // How the result is used, e.g. "U" for a terminal URI
+        service: String,                        // Service and protocol, e.g. "E2U+sip"
+        regexp: String,                         // Substitution applied to the original string
+        replacement: String,                    // Next name to look up when regexp is empty, "" for the root
+    },
+    Tlsa {
+                                /*   https://www.rfc-editor.org/rfc/rfc6698#section-2.1   */
+        usage: u8,                              // Which certificate the data must match, e.g. 3 for the server's own (DANE-EE)
+        selector: u8,                           // 0 matches the whole certificate, 1 only its public key
+        matching_type: u8,                      // 0 is the data itself, 1 and 2 its SHA-256 and SHA-512 digests
+        data: Vec<u8>,                          // Certificate association data, compared according to the three fields above
+    },
+    Raw { rtype: RecordType, bytes: Vec<u8> },  // Any type without a typed form, kept as opaque bytes
+}
+
+impl RData {
+    /// TXT data holding `text`, split into as many 255 byte character-strings as it needs. Splits never fall inside
+    /// a multi-byte UTF-8 character, so a chunk can come out a few bytes short of 255
+    pub fn txt(text: &str) -> RData {
+
+        let mut strings = Vec::new();
+        let mut rest = text;
+
+        while rest.len() > MAX_CHARACTER_STRING_LEN {
+            let mut split = MAX_CHARACTER_STRING_LEN;
+            while !rest.is_char_boundary(split) {
+                split -= 1;
+            }
+
+            let (chunk, remainder) = rest.split_at(split);
+            strings.push(chunk.to_string());
+            rest = remainder;
+        }
+        strings.push(rest.to_string());
+
+        RData::Txt(strings)
+    }
+
+    /// The record type this data belongs in
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            RData::A(_) => RecordType::A,
+            RData::Aaaa(_) => RecordType::Aaaa,
+            RData::Cname(_) => RecordType::Cname,
+            RData::Mx { .. } => RecordType::Mx,
+            RData::Txt(_) => RecordType::Txt,
+            RData::Ns(_) => RecordType::Ns,
+            RData::Soa { .. } => RecordType::Soa,
+            RData::Hinfo { .. } => RecordType::Hinfo,
+            RData::Naptr { .. } => RecordType::Naptr,
+            RData::Tlsa { .. } => RecordType::Tlsa,
+            RData::Raw { rtype, .. } => *rtype,
+        }
+    }
+
+    /// Convert to the wire format rdata. Embedded names are written uncompressed
+    pub fn encode(&self) -> Vec<u8> {
+
+        let mut buffer_vec = Vec::new();
+        self.visit_encoded(|bytes| buffer_vec.extend_from_slice(bytes));
+
+        buffer_vec
+    }
+
+    /// Length of the encoded rdata in bytes - the value that belongs in the rdata length field
+    pub fn encoded_len(&self) -> usize {
+
+        let mut length = 0;
+        self.visit_encoded(|bytes| length += bytes.len());
+
+        length
+    }
+
+    /// Hand the wire format rdata to `visit` a piece at a time, in order. Nothing is allocated for names without
+    /// escapes, so serialize_to_slice can write rdata straight into its buffer
+    pub(crate) fn visit_encoded(&self, mut visit: impl FnMut(&[u8])) {
+
+        match self {
+            RData::A(address) => visit(&address.octets()),
+            RData::Aaaa(address) => visit(&address.octets()),
+            RData::Cname(name) | RData::Ns(name) => visit_name(name, &mut visit),
+            RData::Mx { preference, exchange } => {
+                visit(&preference.to_be_bytes());
+                visit_name(exchange, &mut visit);
+            }
+            RData::Txt(strings) => {
+                for string in strings {
+                    visit_character_string(string, &mut visit);
+                }
+            }
+            RData::Soa { mname, rname, serial, refresh, retry, expire, minimum } => {
+                visit_name(mname, &mut visit);
+                visit_name(rname, &mut visit);
+                for field in [serial, refresh, retry, expire, minimum] {
+                    visit(&field.to_be_bytes());
+                }
+            }
+            RData::Hinfo { cpu, os } => {
+                visit_character_string(cpu, &mut visit);
+                visit_character_string(os, &mut visit);
+            }
+            RData::Naptr { order, preference, flags, service, regexp, replacement } => {
+                visit(&order.to_be_bytes());
+                visit(&preference.to_be_bytes());
+                for string in [flags, service, regexp] {
+                    visit_character_string(string, &mut visit);
+                }
+                visit_name(replacement, &mut visit);
+            }
+            RData::Tlsa { usage, selector, matching_type, data } => {
+                visit(&[*usage, *selector, *matching_type]);
+                visit(data);
+            }
+            RData::Raw { bytes, .. } => visit(bytes),
+        }
+    }
+
+    /// Presentation format as printed by dig and written in zone files: addresses in their usual notation, names as
+    /// absolute names with a trailing dot, TXT as quoted strings, SOA as its seven fields in order, and types without a
+    /// typed form in the RFC 3597 generic `\# <length> <hex>` form
+    pub fn to_text(&self) -> String {
+        match self {
+            RData::A(address) => address.to_string(),
+            RData::Aaaa(address) => address.to_string(),
+            RData::Cname(name) | RData::Ns(name) => absolute(name),
+            RData::Mx { preference, exchange } => format!("{} {}", preference, absolute(exchange)),
+            RData::Txt(strings) => strings.iter().map(|string| quote(string)).collect::<Vec<String>>().join(" "),
+            RData::Soa { mname, rname, serial, refresh, retry, expire, minimum } => format!(
+                "{} {} {} {} {} {} {}",
+                absolute(mname), absolute(rname), serial, refresh, retry, expire, minimum
+            ),
+            RData::Hinfo { cpu, os } => format!("{} {}", quote(cpu), quote(os)),
+            RData::Naptr { order, preference, flags, service, regexp, replacement } => format!(
+                "{} {} {} {} {} {}",
+                order, preference, quote(flags), quote(service), quote(regexp), absolute(replacement)
+            ),
+            RData::Tlsa { usage, selector, matching_type, data } => format!("{} {} {} {}", usage, selector, matching_type, hex(data)),
+            RData::Raw { bytes, .. } => format!("\\# {} {}", bytes.len(), hex(bytes)).trim_end().to_string(),
+        }
+    }
+
+    /// The data's components as (field name, value) pairs in wire order, for tooling that wants to look inside a
+    /// record without matching on every variant. Field names follow the RFCs; names are given without the root dot,
+    /// numbers in decimal and binary data in hex. TXT gives one "text" field per character-string
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            RData::A(address) => vec![("address", address.to_string())],
+            RData::Aaaa(address) => vec![("address", address.to_string())],
+            RData::Cname(name) => vec![("target", name.clone())],
+            RData::Ns(name) => vec![("nsdname", name.clone())],
+            RData::Mx { preference, exchange } => vec![("preference", preference.to_string()), ("exchange", exchange.clone())],
+            RData::Txt(strings) => strings.iter().map(|string| ("text", string.clone())).collect(),
+            RData::Soa { mname, rname, serial, refresh, retry, expire, minimum } => vec![
+                ("mname", mname.clone()),
+                ("rname", rname.clone()),
+                ("serial", serial.to_string()),
+                ("refresh", refresh.to_string()),
+                ("retry", retry.to_string()),
+                ("expire", expire.to_string()),
+                ("minimum", minimum.to_string()),
+            ],
+            RData::Hinfo { cpu, os } => vec![("cpu", cpu.clone()), ("os", os.clone())],
+            RData::Naptr { order, preference, flags, service, regexp, replacement } => vec![
+                ("order", order.to_string()),
+                ("preference", preference.to_string()),
+                ("flags", flags.clone()),
+                ("service", service.clone()),
+                ("regexp", regexp.clone()),
+                ("replacement", replacement.clone()),
+            ],
+            RData::Tlsa { usage, selector, matching_type, data } => vec![
+                ("usage", usage.to_string()),
+                ("selector", selector.to_string()),
+                ("matching_type", matching_type.to_string()),
+                ("data", hex(data)),
+            ],
+            RData::Raw { bytes, .. } => vec![("data", hex(bytes))],
+        }
+    }
+
+    /// Decode the rdata of a record of the given type. Unknown types come back as RData::Raw.
+    /// Names in `rdata` must be uncompressed; use parse_in_message for rdata still sitting inside a message
+    pub fn parse(record_type: RecordType, rdata: &[u8]) -> Result<RData, DnsError> {
+        RData::parse_in_message(record_type, rdata, 0, rdata.len())
+    }
+
+    /// Decode `length` bytes of rdata starting at `offset` in a whole message. Names inside the rdata (CNAME, NS,
+    /// MX exchange, SOA mname/rname) may be compressed, with pointers back into the rest of the message
+    pub fn parse_in_message(record_type: RecordType, message: &[u8], offset: usize, length: usize) -> Result<RData, DnsError> {
+
+        let end = offset + length;
+        let buf = message.get(..end).ok_or(DnsError::InvalidRdata)?;     // Names can point backwards but not run past the rdata
+        let rdata = &buf[offset..];
+
+        let mut offset = offset;
+
+        let parsed = match record_type {
+            RecordType::A => {
+                let octets: [u8; 4] = rdata.try_into().map_err(|_| DnsError::InvalidRdata)?;
+                offset += 4;
+                RData::A(Ipv4Addr::from(octets))
+            }
+            RecordType::Aaaa => {
+                let octets: [u8; 16] = rdata.try_into().map_err(|_| DnsError::InvalidRdata)?;
+                offset += 16;
+                RData::Aaaa(Ipv6Addr::from(octets))
+            }
+            RecordType::Cname => RData::Cname(read_name(buf, &mut offset)?),
+            RecordType::Ns => RData::Ns(read_name(buf, &mut offset)?),
+            RecordType::Mx => {
+                let preference = read_u16(buf, &mut offset)?;
+                RData::Mx { preference, exchange: read_name(buf, &mut offset)? }
+            }
+            RecordType::Txt => {
+                let mut strings = Vec::new();
+                while offset < end {
+                    strings.push(read_character_string(buf, &mut offset)?);
+                }
+                RData::Txt(strings)
+            }
+            RecordType::Hinfo => RData::Hinfo {
+                cpu: read_character_string(buf, &mut offset)?,
+                os: read_character_string(buf, &mut offset)?,
+            },
+            RecordType::Naptr => RData::Naptr {
+                order: read_u16(buf, &mut offset)?,
+                preference: read_u16(buf, &mut offset)?,
+                flags: read_character_string(buf, &mut offset)?,
+                service: read_character_string(buf, &mut offset)?,
+                regexp: read_character_string(buf, &mut offset)?,
+                replacement: read_name(buf, &mut offset)?,
+            },
+            RecordType::Tlsa => {
+                let [usage, selector, matching_type] = *rdata.first_chunk().ok_or(DnsError::InvalidRdata)?;
+                offset = end;
+                RData::Tlsa { usage, selector, matching_type, data: rdata[3..].to_vec() }
+            }
+            RecordType::Soa => RData::Soa {
+                mname: read_name(buf, &mut offset)?,
+                rname: read_name(buf, &mut offset)?,
+                serial: read_u32(buf, &mut offset)?,
+                refresh: read_u32(buf, &mut offset)?,
+                retry: read_u32(buf, &mut offset)?,
+                expire: read_u32(buf, &mut offset)?,
+                minimum: read_u32(buf, &mut offset)?,
+            },
+            rtype => {
+                offset = end;
+                RData::Raw { rtype, bytes: rdata.to_vec() }
+            }
+        };
+
+        // Every byte of the rdata must belong to a field
+        if offset != end {
+            return Err(DnsError::InvalidRdata);
+        }
+
+        Ok(parsed)
+    }
+}
+
+
+/// Conventional date-based SOA serial for today (UTC): YYYYMMDDnn, where nn counts the changes made that day.
+/// `seq` is capped at 99 so it cannot spill into the date digits
+#[cfg(feature = "std")]
+pub fn soa_serial_today(seq: u8) -> u32 {
+
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_date(seconds / 86_400);
+
+    soa_serial(year, month, day, seq)
+}
+
+/// YYYYMMDDnn serial for a given date, for example 2024-03-07 with seq 2 is 2024030702
+pub fn soa_serial(year: u32, month: u32, day: u32, seq: u8) -> u32 {
+    year * 1_000_000 + month * 10_000 + day * 100 + seq.min(99) as u32
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) in the proleptic Gregorian calendar.
+/// Howard Hinnant's days_from_civil inverse - http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+#[cfg(feature = "std")]
+fn civil_date(days: u64) -> (u32, u32, u32) {
+
+    let days = days + 719_468;                                  // Shift the epoch to 0000-03-01, so leap days end the year
+    let era = days / 146_097;                                   // 400 year cycles
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;              // 0 is March
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year as u32, month as u32, day as u32)
+}
+
+/// One piece of rdata as canonical_rdata steps over it
+enum CanonicalField {
+    Fixed(usize),           // That many bytes of numbers or binary data
+    CharacterString,        // A length byte and that much text
+    Name,                   // An uncompressed domain name, lowercased in place
+}
+
+/// Canonical form of stored rdata for DNSSEC (RFC 4034 section 6.2): the bytes as they are, except that the domain
+/// names embedded in the types listed there are lowercased. RFC 6840 section 5.1 took HINFO and NSEC off that list,
+/// so they are copied unchanged like every other type, text and binary fields included. Names are expected
+/// uncompressed, as AnswerSection::parse stores them; rdata cut short is lowercased as far as it goes
+pub(crate) fn canonical_rdata(record_type: RecordType, rdata: &[u8]) -> Vec<u8> {
+
+    use CanonicalField::*;
+
+    let layout: &[CanonicalField] = match u16::from(record_type) {
+        2 | 3 | 4 | 5 | 7 | 8 | 9 | 12 | 30 | 39 => &[Name],   // NS, MD, MF, CNAME, MB, MG, MR, PTR, NXT, DNAME
+        6 | 14 | 17 => &[Name, Name],                           // SOA, MINFO, RP
+        15 | 18 | 21 | 36 => &[Fixed(2), Name],                 // MX, AFSDB, RT, KX
+        26 => &[Fixed(2), Name, Name],                          // PX
+        33 => &[Fixed(6), Name],                                // SRV: priority, weight, port
+        35 => &[Fixed(4), CharacterString, CharacterString, CharacterString, Name],    // NAPTR
+        24 | 46 => &[Fixed(18), Name],                          // SIG, RRSIG: the signer's name follows the fixed fields
+        _ => &[],
+    };
+
+    let mut canonical = rdata.to_vec();
+    let mut offset = 0;
+
+    for field in layout {
+        let Some(next) = (match field {
+            Fixed(length) => Some(offset + length),
+            CharacterString => canonical.get(offset).map(|&length| offset + 1 + length as usize),
+            Name => lowercase_name_at(&mut canonical, offset),
+        }) else {
+            break;
+        };
+        offset = next;
+    }
+
+    canonical
+}
+
+/// Lowercase the labels of the uncompressed name at `offset`, returning the offset just past it. None when the
+/// name runs off the end or holds a compression pointer
+fn lowercase_name_at(rdata: &mut [u8], mut offset: usize) -> Option<usize> {
+
+    loop {
+        let length = *rdata.get(offset)? as usize;
+        if length == 0 {
+            return Some(offset + 1);
+        }
+        if length > MAX_LABEL_LEN {
+            return None;
+        }
+
+        let end = (offset + 1 + length).min(rdata.len());
+        rdata[offset + 1..end].make_ascii_lowercase();
+        offset += 1 + length;
+    }
+}
+
+/// Names are usually stored without the root dot; presentation format writes it to mark the name as absolute.
+/// A name that already ends in one keeps it
+fn absolute(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+/// Bytes as unbroken uppercase hex, the way binary rdata fields are written in zone files
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// Wrap a character-string in double quotes, escaping quotes and backslashes and writing non-printable bytes as \DDD
+fn quote(string: &str) -> String {
+
+    let mut quoted = String::from("\"");
+
+    for &byte in string.as_bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(byte as char);
+            }
+            0x20..=0x7E => quoted.push(byte as char),
+            _ => quoted += &format!("\\{:03}", byte),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// A <character-string>: one length byte, then at most 255 bytes of text (anything longer is cut off)
+fn visit_character_string(string: &str, visit: &mut impl FnMut(&[u8])) {
+    let bytes = &string.as_bytes()[..string.len().min(MAX_CHARACTER_STRING_LEN)];
+    visit(&[bytes.len() as u8]);
+    visit(bytes);
+}
+
+/// An uncompressed name as encode_name writes it: each label behind its length byte, then the root label
+fn visit_name(name: &str, visit: &mut impl FnMut(&[u8])) {
+    visit_name_labels(name, |label| {
+        visit(&[label.len() as u8]);
+        visit(label);
+    });
+    visit(&[0]);
+}
+
+fn read_character_string(rdata: &[u8], offset: &mut usize) -> Result<String, DnsError> {
+    let length = *rdata.get(*offset).ok_or(DnsError::InvalidRdata)? as usize;
+    let bytes = rdata.get(*offset + 1..*offset + 1 + length).ok_or(DnsError::InvalidRdata)?;
+    *offset += 1 + length;
+
+    Ok(String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn read_u16(rdata: &[u8], offset: &mut usize) -> Result<u16, DnsError> {
+    let bytes = rdata.get(*offset..*offset + 2).ok_or(DnsError::InvalidRdata)?;
+    *offset += 2;
+
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(rdata: &[u8], offset: &mut usize) -> Result<u32, DnsError> {
+    let bytes = rdata.get(*offset..*offset + 4).ok_or(DnsError::InvalidRdata)?;
+    *offset += 4;
+
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::name::encode_name;
+
+    fn assert_round_trip(rdata: RData) {
+        let encoded = rdata.encode();
+        assert_eq!(encoded.len(), rdata.encoded_len());
+        assert_eq!(RData::parse(rdata.record_type(), &encoded).unwrap(), rdata);
+    }
+
+    #[test]
+    fn every_variant_round_trips() {
+        assert_round_trip(RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_round_trip(RData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)));
+        assert_round_trip(RData::Cname("target.example.com".to_string()));
+        assert_round_trip(RData::Mx { preference: 10, exchange: "mail.example.com".to_string() });
+        assert_round_trip(RData::Txt(vec!["v=spf1 -all".to_string(), String::new()]));
+        assert_round_trip(RData::Ns("ns1.example.com".to_string()));
+        assert_round_trip(RData::Soa {
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024030701,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1_209_600,
+            minimum: 300,
+        });
+        assert_round_trip(RData::Raw { rtype: RecordType::Unknown(99), bytes: vec![1, 2, 3] });
+    }
+
+    #[test]
+    fn to_text_uses_presentation_format() {
+        assert_eq!(RData::A(Ipv4Addr::new(192, 0, 2, 1)).to_text(), "192.0.2.1");
+        assert_eq!(RData::Mx { preference: 10, exchange: "mail.example.com".to_string() }.to_text(), "10 mail.example.com.");
+        assert_eq!(RData::Mx { preference: 10, exchange: "mail.example.com.".to_string() }.to_text(), "10 mail.example.com.");
+        assert_eq!(RData::Cname(String::new()).to_text(), ".");
+        assert_eq!(RData::Txt(vec!["say \"hi\"".to_string(), "two".to_string()]).to_text(), r#""say \"hi\"" "two""#);
+    }
+
+    #[test]
+    fn soa_serial_is_date_then_sequence() {
+        assert_eq!(soa_serial(2024, 3, 7, 2), 2024030702);
+        assert_eq!(soa_serial(2024, 12, 31, 150), 2024123199);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn civil_date_counts_days_from_the_epoch() {
+        // 2024-03-07 is day 19789 after the epoch
+        assert_eq!(civil_date(19_789), (2024, 3, 7));
+        assert_eq!(civil_date(0), (1970, 1, 1));
+    }
+
+    #[test]
+    fn hinfo_round_trips() {
+        let hinfo = RData::Hinfo { cpu: "AMD64".to_string(), os: "Linux".to_string() };
+
+        assert_eq!(hinfo.encode(), b"\x05AMD64\x05Linux");
+        assert_round_trip(hinfo.clone());
+        assert_eq!(hinfo.to_text(), r#""AMD64" "Linux""#);
+    }
+
+    #[test]
+    fn long_txt_is_split_into_255_byte_strings() {
+        let text = "x".repeat(600);
+
+        let RData::Txt(strings) = RData::txt(&text) else { panic!("RData::txt must build TXT data") };
+
+        assert_eq!(strings.iter().map(String::len).collect::<Vec<usize>>(), vec![255, 255, 90]);
+        assert_eq!(strings.concat(), text);
+        assert_eq!(RData::txt(&text).encoded_len(), 603);
+    }
+
+    #[test]
+    fn naptr_round_trips() {
+        let naptr = RData::Naptr {
+            order: 100,
+            preference: 10,
+            flags: "U".to_string(),
+            service: "E2U+sip".to_string(),
+            regexp: "!^.*$!sip:info@example.com!".to_string(),
+            replacement: String::new(),
+        };
+
+        assert_round_trip(naptr.clone());
+        assert_eq!(naptr.to_text(), r#"100 10 "U" "E2U+sip" "!^.*$!sip:info@example.com!" ."#);
+    }
+
+    #[test]
+    fn tlsa_round_trips() {
+        let tlsa = RData::Tlsa { usage: 3, selector: 1, matching_type: 1, data: vec![0xd2, 0xab, 0xde, 0x24] };
+
+        assert_eq!(tlsa.encode(), vec![3, 1, 1, 0xd2, 0xab, 0xde, 0x24]);
+        assert_round_trip(tlsa.clone());
+        assert_eq!(tlsa.to_text(), "3 1 1 D2ABDE24");
+
+        assert!(matches!(RData::parse(RecordType::Tlsa, &[3, 1]), Err(DnsError::InvalidRdata)));
+    }
+
+    #[test]
+    fn canonical_rdata_lowercases_only_embedded_names() {
+        let mut srv = vec![0, 10, 0, 5, 0x13, 0xC4];
+        srv.extend(encode_name("SIP.Example.COM"));
+        let mut expected = srv[..6].to_vec();
+        expected.extend(encode_name("sip.example.com"));
+        assert_eq!(canonical_rdata(RecordType::from(33), &srv), expected);
+
+        let mut rrsig = vec![0, 1, 13, 2, 0, 0, 0x0E, 0x10, 0x66, 0, 0, 0, 0x65, 0, 0, 0, 0x30, 0x39];
+        rrsig.extend(encode_name("Example.COM"));
+        rrsig.extend_from_slice(b"SIGNATURE");
+        let canonical = canonical_rdata(RecordType::Rrsig, &rrsig);
+        assert_eq!(canonical[..18], rrsig[..18]);
+        assert_eq!(canonical[18..31], encode_name("example.com")[..]);
+        assert_eq!(&canonical[31..], b"SIGNATURE");
+
+        // HINFO left the list in RFC 6840, and plain text is never touched
+        let hinfo = RData::Hinfo { cpu: "AMD64".to_string(), os: "Linux".to_string() }.encode();
+        assert_eq!(canonical_rdata(RecordType::Hinfo, &hinfo), hinfo);
+        assert_eq!(canonical_rdata(RecordType::Txt, b"\x03ABC"), b"\x03ABC");
+    }
+}
@@ -0,0 +1,331 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Instant;
+
+use crate::dns::*;
+use crate::error::DnsError;
+use crate::rdata::RData;
+use crate::resolver::Resolver;
+
+
+const MAX_ITERATIONS: usize = 32;           // Queries allowed for one name before giving up on a delegation chain
+const MAX_NESTED_LOOKUPS: usize = 4;        // How deep nameserver address lookups may nest inside each other
+
+/// IPv4 addresses of the 13 root servers, from the IANA root hints file (named.root)
+pub const ROOT_HINTS: [(&str, Ipv4Addr); 13] = [
+    ("a.root-servers.net", Ipv4Addr::new(198, 41, 0, 4)),
+    ("b.root-servers.net", Ipv4Addr::new(170, 247, 170, 2)),
+    ("c.root-servers.net", Ipv4Addr::new(192, 33, 4, 12)),
+    ("d.root-servers.net", Ipv4Addr::new(199, 7, 91, 13)),
+    ("e.root-servers.net", Ipv4Addr::new(192, 203, 230, 10)),
+    ("f.root-servers.net", Ipv4Addr::new(192, 5, 5, 241)),
+    ("g.root-servers.net", Ipv4Addr::new(192, 112, 36, 4)),
+    ("h.root-servers.net", Ipv4Addr::new(198, 97, 190, 53)),
+    ("i.root-servers.net", Ipv4Addr::new(192, 36, 148, 17)),
+    ("j.root-servers.net", Ipv4Addr::new(192, 58, 128, 30)),
+    ("k.root-servers.net", Ipv4Addr::new(193, 0, 14, 129)),
+    ("l.root-servers.net", Ipv4Addr::new(199, 7, 83, 42)),
+    ("m.root-servers.net", Ipv4Addr::new(202, 12, 27, 33)),
+];
+
+
+impl Resolver {
+    /// Resolve a name on our own, starting from the built-in root hints - no upstream recursive server is involved
+    pub fn resolve_from_root(&self, name: &str, record_type: RecordType) -> Result<DnsPacket, DnsError> {
+
+        let roots: Vec<SocketAddr> = ROOT_HINTS.iter()
+            .map(|&(_, address)| SocketAddr::new(IpAddr::V4(address), 53))
+            .collect();
+
+        self.resolve_iterative(name, record_type, &roots)
+    }
+
+    /// Resolve a name without a recursive upstream: ask `roots` (servers for the root zone), then follow each
+    /// NS referral down the delegation chain until a server answers authoritatively.
+    /// With qname_minimization set, each server is only shown one label more than the zone it serves
+    /// (example.com's servers see `www.example.com NS` rather than the full question) until the final query.
+    /// The whole walk, nameserver lookups included, must finish within resolution_timeout or fails with DnsError::Timeout
+    pub fn resolve_iterative(&self, name: &str, record_type: RecordType, roots: &[SocketAddr]) -> Result<DnsPacket, DnsError> {
+        let deadline = Instant::now() + self.resolution_timeout;
+        self.iterate(name, record_type, roots, 0, deadline)
+    }
+
+    fn iterate(&self, name: &str, record_type: RecordType, roots: &[SocketAddr], depth: usize, deadline: Instant) -> Result<DnsPacket, DnsError> {
+
+        let name = name.trim_end_matches('.');
+        let labels: Vec<&str> = name.split('.').filter(|label| !label.is_empty()).collect();
+
+        let mut servers = roots.to_vec();
+        let mut zone_cut = String::new();       // Zone the current servers are authoritative for, "" being the root
+        let mut revealed = 0;                   // Labels of `name` shown to the servers so far
+
+        for _ in 0..MAX_ITERATIONS {
+            revealed = revealed.max(label_count(&zone_cut));
+
+            // Minimized steps ask for the NS records of the next name down; the last step asks the real question
+            let minimized = self.qname_minimization && revealed + 1 < labels.len();
+            let (query_name, query_type) = if minimized {
+                revealed += 1;
+                (labels[labels.len() - revealed..].join("."), RecordType::Ns)
+            } else {
+                (name.to_string(), record_type)
+            };
+
+            let mut query = self.build_query(QuestionSection::new_query(&query_name, query_type, DnsClass::In));
+            query.header.recursion_desired = false;
+            let response = self.exchange_with_failover(&servers, &query, deadline)?;
+
+            if let Some((child_zone, nameservers)) = referral(&response, &zone_cut, &query_name) {
+                let glue = glue_addresses(&response, &nameservers, &zone_cut, self.nameserver_port);
+                servers = if glue.is_empty() { self.nameserver_addresses(&nameservers, roots, depth, deadline)? } else { glue };
+                zone_cut = child_zone;
+                continue;
+            }
+
+            if !minimized {
+                return Ok(response);
+            }
+
+            // The minimized name is not a zone cut, so keep walking down with the same servers. If the server
+            // objected to the minimized query (NXDOMAIN, SERVFAIL, ...) stop minimizing and ask the full question
+            if ResponseCode::from(response.header.response_code) != ResponseCode::NoError {
+                revealed = labels.len();
+            }
+        }
+
+        Err(DnsError::TooManyReferrals)
+    }
+
+    /// Find addresses for a referral's nameservers by resolving their names from the root, one at a time,
+    /// stopping at the first that resolves
+    fn nameserver_addresses(&self, nameservers: &[String], roots: &[SocketAddr], depth: usize, deadline: Instant) -> Result<Vec<SocketAddr>, DnsError> {
+
+        if depth >= MAX_NESTED_LOOKUPS {
+            return Err(DnsError::LameDelegation);
+        }
+
+        for nameserver in nameservers {
+            let response = match self.iterate(nameserver, RecordType::A, roots, depth + 1, deadline) {
+                Ok(response) => response,
+                Err(DnsError::Timeout) if Instant::now() >= deadline => return Err(DnsError::Timeout),
+                Err(_) => continue,
+            };
+
+            let addresses: Vec<SocketAddr> = response.answers.iter()
+                .filter_map(|answer| match answer.rdata() {
+                    Ok(RData::A(address)) => Some(SocketAddr::new(IpAddr::V4(address), self.nameserver_port)),
+                    _ => None,
+                })
+                .collect();
+
+            if !addresses.is_empty() {
+                return Ok(addresses);
+            }
+        }
+
+        Err(DnsError::LameDelegation)
+    }
+}
+
+
+/// A referral is a non-authoritative, answerless response whose authority section names the nameservers of a
+/// zone below the current zone cut that contains the queried name. Returns that zone and its nameserver names
+fn referral(response: &DnsPacket, zone_cut: &str, query_name: &str) -> Option<(String, Vec<String>)> {
+
+    if !response.answers.is_empty() || response.header.authoritative_answer
+        || ResponseCode::from(response.header.response_code) != ResponseCode::NoError
+    {
+        return None;
+    }
+
+    let mut child_zone: Option<String> = None;
+    let mut nameservers = Vec::new();
+
+    for authority in &response.authorities {
+        let Ok(RData::Ns(nameserver)) = authority.rdata() else { continue };
+        let owner = authority.resource_record.name.trim_end_matches('.').to_ascii_lowercase();
+
+        // Referrals must move strictly down the tree towards the queried name, otherwise the chain could loop
+        if owner == zone_cut || !is_subdomain(&owner, zone_cut) || !is_subdomain(query_name, &owner) {
+            continue;
+        }
+        if child_zone.get_or_insert_with(|| owner.clone()) != &owner {
+            continue;
+        }
+
+        nameservers.push(nameserver);
+    }
+
+    child_zone.map(|zone| (zone, nameservers))
+}
+
+/// Addresses for the referral's nameservers that the referring server already supplied in its additional section.
+/// Glue is only trusted for names inside the referring server's own zone (`zone_cut`), so a server cannot
+/// plant addresses for names it has no authority over. The addresses are paired with `port`
+fn glue_addresses(response: &DnsPacket, nameservers: &[String], zone_cut: &str, port: u16) -> Vec<SocketAddr> {
+
+    response.additionals.iter()
+        .filter(|additional| {
+            let owner = additional.resource_record.name.trim_end_matches('.');
+            is_subdomain(owner, zone_cut)
+                && nameservers.iter().any(|nameserver| nameserver.trim_end_matches('.').eq_ignore_ascii_case(owner))
+        })
+        .filter_map(|additional| match additional.rdata() {
+            Ok(RData::A(address)) => Some(SocketAddr::new(IpAddr::V4(address), port)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// True when `name` is `zone` itself or anywhere below it. Every name is below the root ("")
+fn is_subdomain(name: &str, zone: &str) -> bool {
+
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let zone = zone.trim_end_matches('.').to_ascii_lowercase();
+
+    zone.is_empty() || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+fn label_count(name: &str) -> usize {
+    name.split('.').filter(|label| !label.is_empty()).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mock_udp_server_at;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    /// A root, a com server and an example.com server on three loopback addresses, each logging the queries it
+    /// gets as "<server> <name> <type>" and waiting `delay` before answering
+    struct MockHierarchy {
+        root: SocketAddr,
+        nameserver_port: u16,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockHierarchy {
+        fn start(delay: Duration) -> MockHierarchy {
+
+            let log = Arc::new(Mutex::new(Vec::new()));
+
+            let tld_address = mock_udp_server_at("127.0.0.2:0".parse().unwrap(), logged("com", &log, delay, |query| {
+                delegation(query, "example.com", "ns1.example.com", Ipv4Addr::new(127, 0, 0, 3))
+            }));
+            let nameserver_port = tld_address.port();
+
+            mock_udp_server_at(SocketAddr::new(Ipv4Addr::new(127, 0, 0, 3).into(), nameserver_port), logged("example.com", &log, delay, |query| {
+                let mut response = DnsPacket::response_to(query);
+                response.header.authoritative_answer = true;
+                if RecordType::from(query.questions[0].resource_record.record_type) == RecordType::A {
+                    response.answers.push(AnswerSection::from_rdata("www.example.com", 300, RData::A(Ipv4Addr::new(192, 0, 2, 80))));
+                }
+                response
+            }));
+
+            let root = mock_udp_server_at("127.0.0.1:0".parse().unwrap(), logged("root", &log, delay, |query| {
+                delegation(query, "com", "ns.tld-servers.com", Ipv4Addr::new(127, 0, 0, 2))
+            }));
+
+            MockHierarchy { root, nameserver_port, log }
+        }
+
+        fn resolver(&self, qname_minimization: bool) -> Resolver {
+            let mut resolver = Resolver::builder().qname_minimization(qname_minimization).retries(0).build();
+            resolver.nameserver_port = self.nameserver_port;
+            resolver
+        }
+
+        fn queries(&self) -> Vec<String> {
+            self.log.lock().unwrap().clone()
+        }
+    }
+
+    fn logged(server: &'static str, log: &Arc<Mutex<Vec<String>>>, delay: Duration, respond: impl Fn(&DnsPacket) -> DnsPacket + Send + 'static)
+        -> impl Fn(&DnsPacket) -> Option<DnsPacket> + Send + 'static
+    {
+        let log = Arc::clone(log);
+        move |query| {
+            let question = &query.questions[0].resource_record;
+            log.lock().unwrap().push(format!("{} {} {}", server, question.name, RecordType::from(question.record_type)));
+            thread::sleep(delay);
+            Some(respond(query))
+        }
+    }
+
+    /// Referral to `zone`, served by `nameserver` whose address is given as glue
+    fn delegation(query: &DnsPacket, zone: &str, nameserver: &str, address: Ipv4Addr) -> DnsPacket {
+        let mut response = DnsPacket::response_to(query);
+        response.authorities.push(AnswerSection::from_rdata(zone, 172800, RData::Ns(nameserver.to_string())));
+        response.additionals.push(AnswerSection::from_rdata(nameserver, 172800, RData::A(address)));
+        response
+    }
+
+    #[test]
+    fn minimized_queries_reveal_one_label_per_server() {
+        let hierarchy = MockHierarchy::start(Duration::ZERO);
+
+        let response = hierarchy.resolver(true).resolve_iterative("www.example.com", RecordType::A, &[hierarchy.root]).unwrap();
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(hierarchy.queries(), vec![
+            "root com NS",
+            "com example.com NS",
+            "example.com www.example.com A",
+        ]);
+    }
+
+    #[test]
+    fn follows_referrals_from_the_root_to_the_answer() {
+        let hierarchy = MockHierarchy::start(Duration::ZERO);
+
+        let response = hierarchy.resolver(false).resolve_iterative("www.example.com", RecordType::A, &[hierarchy.root]).unwrap();
+
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.answers[0].rdata().unwrap(), RData::A(Ipv4Addr::new(192, 0, 2, 80)));
+        assert_eq!(hierarchy.queries(), vec![
+            "root www.example.com A",
+            "com www.example.com A",
+            "example.com www.example.com A",
+        ]);
+    }
+
+    #[test]
+    fn glue_saves_the_nameserver_lookup() {
+        let hierarchy = MockHierarchy::start(Duration::ZERO);
+
+        hierarchy.resolver(false).resolve_iterative("www.example.com", RecordType::A, &[hierarchy.root]).unwrap();
+
+        let queries = hierarchy.queries();
+        assert_eq!(queries.len(), 3);
+        assert!(queries.iter().all(|query| !query.contains("ns1.example.com") && !query.contains("ns.tld-servers.com")));
+    }
+
+    #[test]
+    fn glue_outside_the_referring_zone_is_ignored() {
+        let query = DnsPacket::query_multi(1, vec![("www.example.com".to_string(), RecordType::A)]);
+        let nameservers = vec!["ns1.example.com".to_string(), "ns.example.net".to_string()];
+
+        let mut response = delegation(&query, "example.com", "ns1.example.com", Ipv4Addr::new(192, 0, 2, 53));
+        response.additionals.push(AnswerSection::from_rdata("ns.example.net", 300, RData::A(Ipv4Addr::new(203, 0, 113, 53))));
+
+        assert_eq!(glue_addresses(&response, &nameservers, "com", 53), vec![SocketAddr::new(Ipv4Addr::new(192, 0, 2, 53).into(), 53)]);
+    }
+
+    #[test]
+    fn resolution_timeout_bounds_the_whole_chain() {
+        let hierarchy = MockHierarchy::start(Duration::from_millis(300));
+
+        // Every server answers within the per-query timeout, but the three of them together take 900ms
+        let mut resolver = hierarchy.resolver(false);
+        resolver.resolution_timeout = Duration::from_millis(500);
+
+        let started = Instant::now();
+        let result = resolver.resolve_iterative("www.example.com", RecordType::A, &[hierarchy.root]);
+
+        assert!(matches!(result, Err(DnsError::Timeout)));
+        assert!(started.elapsed() < Duration::from_millis(900));
+    }
+}
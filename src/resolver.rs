@@ -0,0 +1,666 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::cache::Cache;
+use crate::dns::*;
+use crate::edns::OptRecord;
+use crate::error::DnsError;
+use crate::name::is_valid_name;
+#[cfg(feature = "async")]
+use crate::nonblocking::unblock;
+
+
+const MIN_UDP_PAYLOAD: usize = 512;     // Every DNS implementation accepts 512 byte datagrams, EDNS or not - RFC 1035 section 2.3.4
+
+
+/// A stub resolver that sends recursive queries to upstream servers over UDP (and TCP for truncated answers).
+/// Build one with Resolver::builder(), or Resolver::new() for a single upstream with the default settings
+pub struct Resolver {
+    pub upstreams: Vec<SocketAddr>,     // Recursive servers queries are sent to, in order - later ones are only tried when earlier ones fail
+    pub timeout: Duration,              // How long to wait for a matching response before giving up
+    pub resolution_timeout: Duration,   // Budget for a whole resolution (every retry and referral included), whatever the per-query timeout
+    pub retries: u32,                   // Extra rounds over the upstream list (each with a fresh id) when every upstream failed
+    pub prefer_ipv6: bool,              // resolve_host asks for AAAA first and only falls back to A when there are none
+    pub dnssec_ok: bool,                // Set the DO bit in the OPT record so upstreams include DNSSEC records
+    pub udp_payload_size: u16,          // Largest UDP response we accept, advertised in the OPT record of every query
+    pub cache: Mutex<Cache>,            // Positive answers, reused until their TTL expires. Locked only around lookups and inserts
+    pub qname_minimization: bool,       // Iterative resolution reveals only one more label to each server - RFC 9156
+    pub(crate) nameserver_port: u16,    // Port nameservers found through referrals are asked on. Always 53, except against test mocks
+}
+
+impl Resolver {
+    pub fn new(upstream: SocketAddr) -> Resolver {
+        Resolver::builder().upstream(upstream).build()
+    }
+
+    pub fn builder() -> ResolverBuilder {
+        ResolverBuilder::new()
+    }
+
+    /// Look up the addresses of a host. Queries A (or AAAA when prefer_ipv6 is set) first and only asks for the
+    /// other family if the first query returned no addresses. Names failing is_valid_name are refused up front
+    pub fn resolve_host(&self, name: &str) -> Result<Vec<IpAddr>, DnsError> {
+
+        if !is_valid_name(name) {
+            return Err(DnsError::InvalidName(name.to_string()));
+        }
+
+        let (first, second) = if self.prefer_ipv6 {
+            (RecordType::Aaaa, RecordType::A)
+        } else {
+            (RecordType::A, RecordType::Aaaa)
+        };
+
+        let addresses = addresses_in(&self.query(name, first)?);
+        if !addresses.is_empty() {
+            return Ok(addresses);
+        }
+
+        Ok(addresses_in(&self.query(name, second)?))
+    }
+
+    /// The first address resolve_host finds for a host, for callers that just need somewhere to connect.
+    /// Fails with DnsError::NoRecords when the name has neither A nor AAAA records
+    pub fn resolve_one(&self, name: &str) -> Result<IpAddr, DnsError> {
+        self.resolve_host(name)?.first().copied().ok_or(DnsError::NoRecords)
+    }
+
+    /// Build the recursive query asking `question`. Every query carries an OPT record advertising udp_payload_size;
+    /// upstreams that reject EDNS are retried without it (see exchange_with)
+    pub(crate) fn build_query(&self, question: QuestionSection) -> DnsPacket {
+
+        let mut query = DnsPacket::new();
+        query.header.id = random_id();
+        query.header.recursion_desired = true;
+
+        query.questions.push(question);
+
+        let mut opt = OptRecord::new();
+        opt.udp_payload_size = self.udp_payload_size;
+        opt.set_dnssec_ok(self.dnssec_ok);
+        query.edns = Some(opt);
+
+        query
+    }
+
+    /// Resolve a question and return the whole parsed response - rcode, TTLs, authority and additional sections included.
+    /// Answered from the cache when possible, otherwise by querying the upstreams. Upstreams are tried in order, moving on to the
+    /// next one when a server times out or fails; after a full pass over the list another round starts (with a fresh
+    /// id) until `retries` extra rounds have been used. Successful answers are cached for their min_ttl
+    pub fn query(&self, name: &str, record_type: RecordType) -> Result<DnsPacket, DnsError> {
+        self.query_question(QuestionSection::new_query(name, record_type, DnsClass::In))
+    }
+
+    /// query for a question that is already built, such as one parsed from a client's query. The question goes
+    /// upstream as it is, class and wire_name included, so a relayed name keeps its exact bytes and letter case
+    pub fn query_question(&self, question: QuestionSection) -> Result<DnsPacket, DnsError> {
+
+        let query = self.build_query(question);
+
+        let cache_key = query.questions[0].cache_key();
+        if let Some(cached) = self.cache.lock().expect("cache lock poisoned").get(&cache_key) {
+            return Ok(cached);
+        }
+
+        let deadline = Instant::now() + self.resolution_timeout;
+        let response = self.exchange_with_failover(&self.upstreams, &query, deadline)?;
+
+        // Question-less error replies are accepted (see echoes_question) but never cached
+        if ResponseCode::from(response.header.response_code) == ResponseCode::NoError
+            && !response.answers.is_empty()
+            && !response.questions.is_empty()
+            && let Some(ttl) = response.min_ttl()
+        {
+            self.cache.lock().expect("cache lock poisoned").insert(cache_key, response.clone(), ttl);
+        }
+
+        Ok(response)
+    }
+
+    /// Fetch a whole zone with a full zone transfer (AXFR, RFC 5936) over TCP, from the first upstream that gives
+    /// it. The server's framed messages are read until the SOA that opened the transfer comes round again, and the
+    /// records are returned in the order they were sent: the SOA first, then the rest of the zone without the
+    /// closing SOA. Each message has `timeout` to arrive. An error rcode is DnsError::TransferRefused. Transfers
+    /// bypass the cache
+    pub fn transfer_zone(&self, zone: &str) -> Result<Vec<ResourceRecord>, DnsError> {
+
+        let mut last_error = DnsError::NoUpstreams;
+
+        for &upstream in &self.upstreams {
+            match self.transfer_zone_from(upstream, zone) {
+                Ok(records) => return Ok(records),
+                Err(error) => last_error = error,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// transfer_zone against one upstream
+    fn transfer_zone_from(&self, upstream: SocketAddr, zone: &str) -> Result<Vec<ResourceRecord>, DnsError> {
+
+        let mut query = self.build_query(QuestionSection::new_query(zone, RecordType::Axfr, DnsClass::In));
+        query.header.recursion_desired = false;
+
+        let mut stream = TcpStream::connect_timeout(&upstream, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        query.write_to_writer(&mut stream)?;
+
+        let mut records: Vec<ResourceRecord> = Vec::new();
+
+        loop {
+            let message = DnsPacket::parse_from_reader(&mut stream)?;
+
+            // Messages after the first may leave the question out - RFC 5936 section 2.2.1
+            if message.header.id != query.header.id
+                || !message.header.query_indicator
+                || (!message.questions.is_empty() && !echoes_question(&query, &message))
+            {
+                return Err(DnsError::MismatchedResponse);
+            }
+            if ResponseCode::from(message.header.response_code) != ResponseCode::NoError {
+                return Err(DnsError::TransferRefused(message.header.response_code));
+            }
+
+            for answer in message.answers {
+                let is_soa = RecordType::from(answer.resource_record.record_type) == RecordType::Soa;
+                match (records.is_empty(), is_soa) {
+                    (true, false) => return Err(DnsError::InvalidTransfer),
+                    (false, true) => return Ok(records),
+                    _ => records.push(answer.resource_record),
+                }
+            }
+        }
+    }
+
+    /// Walk a server list (round after round, up to `retries` extra rounds) until one of them answers.
+    /// Gives up with DnsError::Timeout once `deadline` has passed, even if servers are left to try
+    pub(crate) fn exchange_with_failover(&self, servers: &[SocketAddr], query: &DnsPacket, deadline: Instant) -> Result<DnsPacket, DnsError> {
+
+        let mut last_error = DnsError::NoUpstreams;
+        let mut query = query.clone();
+
+        for round in 0..=self.retries {
+            if round > 0 {
+                query = query.with_new_id(random_id());
+            }
+
+            for &upstream in servers {
+                if Instant::now() >= deadline {
+                    return Err(DnsError::Timeout);
+                }
+
+                match self.exchange_with(upstream, &query, deadline) {
+                    Ok(response) => return Ok(response),
+                    Err(error) => last_error = error,
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Send one query to one upstream, repeating it without the OPT record if the upstream rejects EDNS
+    fn exchange_with(&self, upstream: SocketAddr, query: &DnsPacket, deadline: Instant) -> Result<DnsPacket, DnsError> {
+
+        let response = self.exchange_framed(upstream, query, deadline)?;
+
+        // Servers that predate EDNS answer an OPT record with FORMERR - RFC 6891 section 7. Ask them again without it
+        if query.edns.is_some() && ResponseCode::from(response.header.response_code) == ResponseCode::FormErr {
+            let mut plain_query = query.with_new_id(random_id());
+            plain_query.edns = None;
+            return self.exchange_framed(upstream, &plain_query, deadline);
+        }
+
+        Ok(response)
+    }
+
+    /// Send the query over UDP. A truncated UDP reply is thrown away and the query repeated over TCP, which has no
+    /// size limit, so callers always see the complete answer. The query gets `timeout`, cut short when the overall
+    /// `deadline` comes first
+    fn exchange_framed(&self, upstream: SocketAddr, query: &DnsPacket, deadline: Instant) -> Result<DnsPacket, DnsError> {
+
+        let deadline = deadline.min(Instant::now() + self.timeout);
+
+        let response = self.exchange_udp(upstream, query, deadline)?;
+        if !response.is_truncated() {
+            return Ok(response);
+        }
+
+        self.exchange_tcp(upstream, query, deadline)
+    }
+
+    /// Send the query in one datagram and wait for the response carrying the same transaction id
+    fn exchange_udp(&self, upstream: SocketAddr, query: &DnsPacket, deadline: Instant) -> Result<DnsPacket, DnsError> {
+
+        let local_address = if upstream.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(local_address)?;
+        socket.connect(upstream)?;
+        socket.send(&query.serialize_to_bytes())?;
+
+        // Datagrams with the wrong id (late answers, spoofing attempts) are skipped until the deadline passes
+        let mut recv_buffer = vec![0; (self.udp_payload_size as usize).max(MIN_UDP_PAYLOAD)];
+
+        loop {
+            socket.set_read_timeout(Some(remaining_until(deadline)?))?;
+
+            let number_of_bytes = socket.recv(&mut recv_buffer)?;
+            if let Ok(response) = DnsPacket::parse(&recv_buffer[..number_of_bytes])
+                && is_response_to(query, &response)
+            {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Send the query over a fresh TCP connection, where each message is prefixed with its 2 byte length - RFC 1035 section 4.2.2
+    fn exchange_tcp(&self, upstream: SocketAddr, query: &DnsPacket, deadline: Instant) -> Result<DnsPacket, DnsError> {
+
+        let mut stream = TcpStream::connect_timeout(&upstream, remaining_until(deadline)?)?;
+        let remaining = remaining_until(deadline)?;
+        stream.set_read_timeout(Some(remaining))?;
+        stream.set_write_timeout(Some(remaining))?;
+
+        query.write_to_writer(&mut stream)?;
+
+        let response = DnsPacket::parse_from_reader(&mut stream)?;
+        if !is_response_to(query, &response) {
+            return Err(DnsError::MismatchedResponse);
+        }
+
+        Ok(response)
+    }
+}
+
+/// Awaitable versions of the lookups above, for callers inside an async runtime. Each one runs its blocking
+/// counterpart on a helper thread (see nonblocking::unblock), so they share the cache, failover and TCP fallback
+/// and never stall an executor thread while waiting on an upstream. They take the Resolver behind an Arc, which
+/// the helper thread holds on to for as long as the lookup runs
+#[cfg(feature = "async")]
+impl Resolver {
+    /// resolve_host, awaited
+    pub async fn resolve_host_async(self: &Arc<Self>, name: &str) -> Result<Vec<IpAddr>, DnsError> {
+        let (resolver, name) = (Arc::clone(self), name.to_string());
+        unblock(move || resolver.resolve_host(&name)).await
+    }
+
+    /// resolve_one, awaited
+    pub async fn resolve_one_async(self: &Arc<Self>, name: &str) -> Result<IpAddr, DnsError> {
+        let (resolver, name) = (Arc::clone(self), name.to_string());
+        unblock(move || resolver.resolve_one(&name)).await
+    }
+
+    /// query, awaited
+    pub async fn query_async(self: &Arc<Self>, name: &str, record_type: RecordType) -> Result<DnsPacket, DnsError> {
+        let (resolver, name) = (Arc::clone(self), name.to_string());
+        unblock(move || resolver.query(&name, record_type)).await
+    }
+
+    /// query_question, awaited
+    pub async fn query_question_async(self: &Arc<Self>, question: QuestionSection) -> Result<DnsPacket, DnsError> {
+        let resolver = Arc::clone(self);
+        unblock(move || resolver.query_question(question)).await
+    }
+}
+
+
+
+/// Fluent configuration for a Resolver: Resolver::builder().upstream(addr).timeout(duration).build()
+pub struct ResolverBuilder {
+    upstreams: Vec<SocketAddr>,
+    timeout: Duration,
+    resolution_timeout: Duration,
+    retries: u32,
+    cache_size: usize,
+    prefer_ipv6: bool,
+    dnssec_ok: bool,
+    udp_payload_size: u16,
+    qname_minimization: bool,
+}
+
+impl ResolverBuilder {
+    pub fn new() -> ResolverBuilder {
+        ResolverBuilder {
+            upstreams: Vec::new(),
+            timeout: Duration::from_secs(5),
+            resolution_timeout: Duration::from_secs(30),
+            retries: 2,
+            cache_size: 1024,
+            prefer_ipv6: false,
+            dnssec_ok: false,
+            udp_payload_size: 1232,
+            qname_minimization: false,
+        }
+    }
+
+    /// Add an upstream server. Call once per server, primary first
+    pub fn upstream(mut self, upstream: SocketAddr) -> ResolverBuilder {
+        self.upstreams.push(upstream);
+        self
+    }
+
+    /// Replace the whole upstream list
+    pub fn upstreams(mut self, upstreams: Vec<SocketAddr>) -> ResolverBuilder {
+        self.upstreams = upstreams;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> ResolverBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Upper bound on a whole resolution, across retries, failover and (for iterative resolution) every referral
+    pub fn resolution_timeout(mut self, resolution_timeout: Duration) -> ResolverBuilder {
+        self.resolution_timeout = resolution_timeout;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> ResolverBuilder {
+        self.retries = retries;
+        self
+    }
+
+    /// Maximum number of cached responses, 0 turns the cache off
+    pub fn cache_size(mut self, cache_size: usize) -> ResolverBuilder {
+        self.cache_size = cache_size;
+        self
+    }
+
+    pub fn prefer_ipv6(mut self, prefer_ipv6: bool) -> ResolverBuilder {
+        self.prefer_ipv6 = prefer_ipv6;
+        self
+    }
+
+    pub fn dnssec_ok(mut self, dnssec_ok: bool) -> ResolverBuilder {
+        self.dnssec_ok = dnssec_ok;
+        self
+    }
+
+    /// UDP payload size to advertise, 1232 by default - the size recommended to avoid IP fragmentation (DNS Flag Day 2020).
+    /// Upstreams truncate responses larger than this, and those are then fetched over TCP
+    pub fn udp_payload_size(mut self, udp_payload_size: u16) -> ResolverBuilder {
+        self.udp_payload_size = udp_payload_size;
+        self
+    }
+
+    pub fn qname_minimization(mut self, qname_minimization: bool) -> ResolverBuilder {
+        self.qname_minimization = qname_minimization;
+        self
+    }
+
+    pub fn build(self) -> Resolver {
+        Resolver {
+            upstreams: self.upstreams,
+            timeout: self.timeout,
+            resolution_timeout: self.resolution_timeout,
+            retries: self.retries,
+            prefer_ipv6: self.prefer_ipv6,
+            dnssec_ok: self.dnssec_ok,
+            udp_payload_size: self.udp_payload_size,
+            cache: Mutex::new(Cache::new(self.cache_size)),
+            qname_minimization: self.qname_minimization,
+            nameserver_port: 53,
+        }
+    }
+}
+
+impl Default for ResolverBuilder {
+    fn default() -> ResolverBuilder {
+        ResolverBuilder::new()
+    }
+}
+
+/// Same transaction id, QR set, and the question repeated back
+fn is_response_to(query: &DnsPacket, response: &DnsPacket) -> bool {
+    response.header.id == query.header.id
+        && response.header.query_indicator
+        && echoes_question(query, response)
+}
+
+/// A response must repeat the question it answers. Some error replies (FORMERR, SERVFAIL) carry no question section
+/// at all, and only those are matched on the transaction id alone: an error rcode with an empty answer section.
+/// Anything that could be cached needs the question, or a forged reply would only have to guess the id
+fn echoes_question(query: &DnsPacket, response: &DnsPacket) -> bool {
+
+    if response.questions.is_empty() {
+        return ResponseCode::from(response.header.response_code) != ResponseCode::NoError && response.answers.is_empty();
+    }
+
+    response.questions.len() == query.questions.len()
+        && query.questions.iter().zip(&response.questions).all(|(asked, echoed)| {
+            asked.resource_record.name.eq_ignore_ascii_case(&echoed.resource_record.name)
+                && asked.resource_record.record_type == echoed.resource_record.record_type
+                && asked.resource_record.class == echoed.resource_record.class
+        })
+}
+
+
+/// Collect every well-formed A and AAAA address from the answer section
+fn addresses_in(response: &DnsPacket) -> Vec<IpAddr> {
+    response.answers.iter()
+        .filter_map(|answer| answer.as_ipv4().map(IpAddr::V4).or_else(|| answer.as_ipv6().map(IpAddr::V6)))
+        .collect()
+}
+
+/// Time left before `deadline`, or DnsError::Timeout when it has already passed (socket timeouts cannot be zero)
+fn remaining_until(deadline: Instant) -> Result<Duration, DnsError> {
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(DnsError::Timeout);
+    }
+
+    Ok(remaining)
+}
+
+/// Unpredictable transaction ids make off-path response spoofing harder. RandomState is seeded randomly per process
+fn random_id() -> u16 {
+    RandomState::new().hash_one(Instant::now()) as u16
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use crate::rdata::RData;
+    use crate::test_support::{mock_tcp_server_at, mock_udp_server};
+
+    /// A mock upstream holding one IPv4 and one IPv6 address for every name
+    fn dual_stack_upstream() -> SocketAddr {
+        mock_udp_server(|query| {
+            let rdata = match RecordType::from(query.questions[0].resource_record.record_type) {
+                RecordType::A => RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                RecordType::Aaaa => RData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+                _ => return Some(DnsPacket::response_to(query)),
+            };
+            Some(DnsPacket::answer_with(query, vec![rdata], 300))
+        })
+    }
+
+    #[test]
+    fn prefer_ipv6_asks_for_aaaa_first() {
+        let upstream = dual_stack_upstream();
+
+        let resolver = Resolver::builder().upstream(upstream).prefer_ipv6(true).build();
+        assert!(resolver.resolve_host("www.example.com").unwrap()[0].is_ipv6());
+
+        let resolver = Resolver::builder().upstream(upstream).build();
+        assert!(resolver.resolve_host("www.example.com").unwrap()[0].is_ipv4());
+    }
+
+    #[test]
+    fn dnssec_ok_sets_do_in_every_query() {
+        let resolver = Resolver::builder().dnssec_ok(true).build();
+
+        let wire = resolver.build_query(QuestionSection::new_query("example.com", RecordType::A, DnsClass::In)).serialize_to_bytes();
+        let query = DnsPacket::parse(&wire).unwrap();
+
+        assert!(query.edns.unwrap().dnssec_ok());
+    }
+
+    #[test]
+    fn only_error_replies_may_leave_out_the_question() {
+        let resolver = Resolver::builder().build();
+        let query = resolver.build_query(QuestionSection::new_query("example.com", RecordType::A, DnsClass::In));
+
+        let servfail = DnsPacket::error_response(query.header.id, None, ResponseCode::ServFail);
+        assert!(is_response_to(&query, &servfail));
+
+        // A forged answer that only matches the id
+        let mut forged = DnsPacket::answer_with(&query, vec![RData::A(Ipv4Addr::new(203, 0, 113, 1))], 300);
+        forged.questions.clear();
+        assert!(!is_response_to(&query, &forged));
+
+        let mut empty_noerror = DnsPacket::response_to(&query);
+        empty_noerror.questions.clear();
+        assert!(!is_response_to(&query, &empty_noerror));
+    }
+
+    #[test]
+    fn truncated_udp_reply_is_repeated_over_tcp() {
+        let upstream = mock_udp_server(|query| {
+            let mut truncated = DnsPacket::response_to(query);
+            truncated.header.truncation = true;
+            Some(truncated)
+        });
+        mock_tcp_server_at(upstream, |query| Some(DnsPacket::answer_with(query, vec![RData::A(Ipv4Addr::new(192, 0, 2, 9))], 300)));
+
+        let resolver = Resolver::new(upstream);
+        let response = resolver.query("example.com", RecordType::A).unwrap();
+
+        assert!(!response.is_truncated());
+        assert_eq!(addresses_in(&response), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 9))]);
+    }
+
+    #[test]
+    fn builder_keeps_every_setting() {
+        let primary: SocketAddr = "192.0.2.1:53".parse().unwrap();
+        let secondary: SocketAddr = "192.0.2.2:53".parse().unwrap();
+
+        let resolver = Resolver::builder()
+            .upstream(primary)
+            .upstream(secondary)
+            .timeout(Duration::from_millis(750))
+            .cache_size(16)
+            .prefer_ipv6(true)
+            .build();
+
+        assert_eq!(resolver.upstreams, vec![primary, secondary]);
+        assert_eq!(resolver.timeout, Duration::from_millis(750));
+        assert_eq!(resolver.retries, 2);
+        assert_eq!(resolver.cache.lock().unwrap().max_entries, 16);
+        assert!(resolver.prefer_ipv6);
+    }
+
+    #[test]
+    fn dead_upstream_fails_over_to_the_next() {
+        let dead = mock_udp_server(|_| None);
+        let working = dual_stack_upstream();
+
+        let resolver = Resolver::builder()
+            .upstream(dead)
+            .upstream(working)
+            .timeout(Duration::from_millis(200))
+            .retries(0)
+            .build();
+
+        assert_eq!(resolver.resolve_one("www.example.com").unwrap(), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn addresses_in_skips_malformed_and_other_records() {
+        let query = DnsPacket::query_multi(1, vec![("example.com".to_string(), RecordType::A)]);
+        let mut response = DnsPacket::answer_with(&query, vec![
+            RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            RData::Aaaa(Ipv6Addr::LOCALHOST),
+            RData::Cname("alias.example.com".to_string()),
+        ], 300);
+        response.answers.push(AnswerSection::from_rdata("example.com", 300, RData::Raw { rtype: RecordType::A, bytes: vec![1, 2] }));
+
+        assert_eq!(addresses_in(&response), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+    }
+
+    #[test]
+    fn query_returns_the_whole_response() {
+        let upstream = mock_udp_server(|query| {
+            if query.questions[0].resource_record.name == "missing.example.com" {
+                let mut nxdomain = DnsPacket::response_to(query);
+                nxdomain.header.response_code = ResponseCode::NxDomain.into();
+                return Some(nxdomain);
+            }
+            Some(DnsPacket::answer_with(query, vec![RData::A(Ipv4Addr::new(192, 0, 2, 1)), RData::A(Ipv4Addr::new(192, 0, 2, 2))], 300))
+        });
+        let resolver = Resolver::new(upstream);
+
+        let response = resolver.query("www.example.com", RecordType::A).unwrap();
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(ResponseCode::from(response.header.response_code), ResponseCode::NoError);
+
+        let response = resolver.query("missing.example.com", RecordType::A).unwrap();
+        assert!(response.answers.is_empty());
+        assert_eq!(ResponseCode::from(response.header.response_code), ResponseCode::NxDomain);
+    }
+
+    #[test]
+    fn edns_formerr_is_retried_without_opt() {
+        let upstream = mock_udp_server(|query| {
+            if query.edns.is_some() {
+                return Some(DnsPacket::error_response(query.header.id, query.questions.first().cloned(), ResponseCode::FormErr));
+            }
+            Some(DnsPacket::answer_with(query, vec![RData::A(Ipv4Addr::new(192, 0, 2, 1))], 300))
+        });
+
+        let response = Resolver::new(upstream).query("example.com", RecordType::A).unwrap();
+
+        assert_eq!(ResponseCode::from(response.header.response_code), ResponseCode::NoError);
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn opt_record_advertises_the_configured_payload_size() {
+        let resolver = Resolver::builder().udp_payload_size(4096).build();
+
+        let query = DnsPacket::parse(&resolver.build_query(QuestionSection::new_query("example.com", RecordType::A, DnsClass::In)).serialize_to_bytes()).unwrap();
+
+        assert_eq!(query.edns.unwrap().udp_payload_size, 4096);
+        assert_eq!(Resolver::builder().build().build_query(QuestionSection::new_query("example.com", RecordType::A, DnsClass::In)).edns.unwrap().udp_payload_size, 1232);
+    }
+
+    #[test]
+    fn resolve_one_returns_the_first_address() {
+        let upstream = mock_udp_server(|query| {
+            if query.questions[0].resource_record.name == "empty.example.com" {
+                return Some(DnsPacket::response_to(query));
+            }
+            Some(DnsPacket::answer_with(query, vec![RData::A(Ipv4Addr::new(192, 0, 2, 7)), RData::A(Ipv4Addr::new(192, 0, 2, 8))], 300))
+        });
+        let resolver = Resolver::new(upstream);
+
+        assert_eq!(resolver.resolve_one("www.example.com").unwrap(), IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7)));
+        assert!(matches!(resolver.resolve_one("empty.example.com"), Err(DnsError::NoRecords)));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_lookups_match_the_blocking_ones() {
+        use crate::test_support::block_on;
+
+        let resolver = Arc::new(Resolver::builder().upstream(dual_stack_upstream()).prefer_ipv6(true).build());
+
+        let addresses = block_on(resolver.resolve_host_async("example.com")).unwrap();
+        assert_eq!(addresses, resolver.resolve_host("example.com").unwrap());
+        assert_eq!(addresses, vec![IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))]);
+
+        let response = block_on(resolver.query_async("example.com", RecordType::A)).unwrap();
+        assert_eq!(addresses_in(&response), vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        assert_eq!(block_on(resolver.resolve_one_async("example.com")).unwrap(), addresses[0]);
+    }
+}
@@ -0,0 +1,1151 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(feature = "async")]
+use std::net::UdpSocket;
+#[cfg(feature = "async")]
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::thread;
+
+use crate::blocklist::Blocklist;
+
+use crate::dns::*;
+use crate::edns::OptRecord;
+use crate::error::DnsError;
+#[cfg(feature = "async")]
+use crate::nonblocking::unblock;
+use crate::ratelimit::RateLimiter;
+use crate::rdata::RData;
+use crate::resolver::Resolver;
+use crate::zone::ZoneStore;
+
+
+const MIN_UDP_PAYLOAD_SIZE: usize = 512;     // Every client takes UDP replies this large, EDNS or not - RFC 1035 section 4.2.1
+const MAX_CNAME_CHAIN: usize = 8;       // CNAMEs followed within our own data before answering with what we have
+const SINKHOLE_TTL: u32 = 60;           // TTL of sinkhole answers, kept short so unblocking a name takes effect quickly
+const MAX_TRANSFER_MESSAGE_LEN: usize = 16384;  // Zone transfers start a new message once one grows past this
+const MAX_TCP_MESSAGE_LEN: usize = u16::MAX as usize;  // Longest message the TCP length prefix can describe - RFC 1035 section 4.2.2
+
+
+/// Answers queries for the names held in its zone store, and optionally relays everything else upstream.
+/// Everything takes &self, so one Server behind an Arc can answer queries on many threads at once
+pub struct Server {
+    pub zone: ZoneStore,
+    pub forwarder: Option<Resolver>,            // Names the zone store doesn't know are relayed here; without one they get NXDOMAIN
+    pub version: Option<String>,                // Reported for CH class TXT queries of version.bind / version.server; None refuses them
+    pub force_ttl: Option<u32>,                 // Every record we send out gets this TTL instead of its own - handy when testing
+    pub minimal_responses: bool,                // Positive answers leave out the authority and additional sections to keep responses small
+    pub udp_payload_size: u16,                  // Advertised in the OPT record of responses to EDNS queries
+    pub padding_block_size: Option<usize>,      // Responses to queries carrying an EDNS padding option are padded to a multiple of this
+    pub reject_unknown_types: bool,             // Questions for a type RecordType has no variant for get NOTIMP instead of an empty answer
+    pub allow_transfer: bool,                   // AXFR queries over TCP get the whole zone; without this they are refused
+    pub blocklist: Blocklist,                   // Names answered with sinkhole_ip instead of being looked up (ad blocking)
+    pub sinkhole_ip: IpAddr,                    // Address handed out for blocklisted names, 0.0.0.0 by default
+    pub rate_limiter: Option<RateLimiter<IpAddr>>,  // Queries from a source over its rate are dropped, which blunts reflection attacks
+    pub response_rate_limiter: Option<RateLimiter<(IpAddr, String, u8)>>,  // Repeats of one response to one source over this rate go out truncated (RRL)
+}
+
+impl Server {
+    pub fn new(zone: ZoneStore) -> Server {
+        Server {
+            zone,
+            forwarder: None,
+            version: None,
+            force_ttl: None,
+            minimal_responses: false,
+            udp_payload_size: 1232,
+            padding_block_size: None,
+            reject_unknown_types: false,
+            allow_transfer: false,
+            blocklist: Blocklist::new(),
+            sinkhole_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            rate_limiter: None,
+            response_rate_limiter: None,
+        }
+    }
+
+    /// handle_query for a datagram received from `source`, with the rate limits applied. A source over
+    /// rate_limiter is dropped without a reply. A source asking for the same response faster than
+    /// response_rate_limiter allows gets it truncated (TC set, no records), so a genuine client retries over TCP
+    /// while a forged one gains no amplification. Servers reachable from the internet should use this, as forged
+    /// sources are how DNS servers get used to amplify attacks against a victim
+    pub fn handle_query_from(&self, request: &[u8], source: SocketAddr) -> Option<Vec<u8>> {
+
+        if let Some(rate_limiter) = &self.rate_limiter
+            && !rate_limiter.allow(source.ip())
+        {
+            return None;
+        }
+
+        self.reply(request, Some(source), true)
+    }
+
+    /// Build the reply to one received datagram. None means the datagram should be dropped without a reply.
+    /// A reply too large for the client's UDP buffer (512 bytes, or the size in the query's OPT record) goes out
+    /// truncated, see reply
+    pub fn handle_query(&self, request: &[u8]) -> Option<Vec<u8>> {
+        self.reply(request, None, true)
+    }
+
+    /// Answer every query arriving on one TCP connection. Messages are preceded by their length as a big endian u16
+    /// in both directions, and a client may send several before closing (RFC 7766). Returns Ok once the client
+    /// closes the connection between messages; a socket error or timeout ends the connection with that error.
+    /// No rate limits apply: a TCP client has completed a handshake, so its address cannot be forged.
+    /// AXFR and IXFR queries get a zone transfer here, see zone_transfer
+    pub fn serve_tcp<S: Read + Write>(&self, stream: &mut S) -> Result<(), DnsError> {
+
+        loop {
+            let mut length_prefix = [0; 2];
+            match stream.read_exact(&mut length_prefix) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(error) => return Err(error.into()),
+            }
+
+            let mut request = vec![0; u16::from_be_bytes(length_prefix) as usize];
+            stream.read_exact(&mut request)?;
+
+            if let Some(messages) = self.zone_transfer(&request) {
+                for message in messages {
+                    message.write_to_writer(stream)?;
+                }
+                continue;
+            }
+
+            let Some(response) = self.reply(&request, None, false) else {
+                continue;
+            };
+
+            stream.write_all(&frame_tcp(&response)?)?;
+        }
+    }
+
+    /// The messages answering an AXFR or IXFR query, or None when `request` is not one. The zone goes out as its
+    /// SOA, every other record, then the SOA again, spread over as many messages as it takes (RFC 5936).
+    /// We keep no history of changes, so IXFR (RFC 1995) gets just the SOA when the serial in the query's authority
+    /// section is already current, and the full zone in the AXFR layout otherwise, which section 4 allows
+    fn zone_transfer(&self, request: &[u8]) -> Option<Vec<DnsPacket>> {
+
+        let query = DnsPacket::parse(request).ok()?;
+        let question = query.questions.first()?;
+        let record_type = RecordType::from(question.resource_record.record_type);
+        if validate_query(&query).is_err() || !matches!(record_type, RecordType::Axfr | RecordType::Ixfr) {
+            return None;
+        }
+
+        let soa = match self.transfer_soa(question) {
+            Ok(soa) => soa,
+            Err(rcode) => return Some(vec![DnsPacket::error_response(query.header.id, Some(question.clone()), rcode)]),
+        };
+
+        let mut first_message = DnsPacket::response_to(&query);
+        first_message.header.authoritative_answer = true;
+        first_message.header.recursion_desired = false;
+
+        let client_serial = query.authorities.first().and_then(|authority| soa_serial(&authority.resource_record));
+        if record_type == RecordType::Ixfr
+            && let (Some(client_serial), Some(serial)) = (client_serial, soa_serial(&soa))
+            && serial_at_least(client_serial, serial)
+        {
+            first_message.answers.push(AnswerSection { resource_record: soa });
+            return Some(vec![first_message]);
+        }
+
+        let contents = self.zone.zone_contents(&question.resource_record.name).unwrap_or_else(|| vec![soa]);
+
+        let mut messages = vec![first_message.clone()];
+        let mut message_len = 0;
+
+        // contents starts with the SOA, which also closes the transfer
+        for record in contents.iter().chain(contents.first()) {
+            let record_len = record.name.len() + 12 + record.rdata.encoded_len();     // Uncompressed name, fixed fields, rdata
+
+            if message_len + record_len > MAX_TRANSFER_MESSAGE_LEN && message_len > 0 {
+                messages.push(first_message.clone());
+                message_len = 0;
+            }
+
+            if let Some(message) = messages.last_mut() {
+                message.answers.push(AnswerSection { resource_record: record.clone() });
+            }
+            message_len += record_len;
+        }
+
+        Some(messages)
+    }
+
+    /// The SOA of the zone a transfer question names. REFUSED unless allow_transfer is set, NOTAUTH when the
+    /// name is not the apex of a zone we hold
+    fn transfer_soa(&self, question: &QuestionSection) -> Result<ResourceRecord, ResponseCode> {
+
+        if !self.allow_transfer {
+            return Err(ResponseCode::Refused);
+        }
+
+        self.zone.lookup(&question.resource_record.name, RecordType::Soa, DnsClass::Any)
+            .into_iter()
+            .next()
+            .ok_or(ResponseCode::NotAuth)
+    }
+
+    /// The serialized reply to `request`. Over UDP, a reply longer than the client can receive is replaced by its
+    /// truncated form (TC set, no records) so the client retries over TCP. The limit is the payload size in the
+    /// query's OPT record, capped at our own udp_payload_size and never below 512; without EDNS it is 512.
+    /// Over TCP the limit is the 65535 bytes a length prefix can describe
+    fn reply(&self, request: &[u8], source: Option<SocketAddr>, over_udp: bool) -> Option<Vec<u8>> {
+
+        let query = match DnsPacket::parse(request) {
+            Ok(query) => query,
+            Err(DnsError::BufferTooShort) if request.len() < 2 => return None,     // Not even an id to reply to
+            Err(_) => return Some(format_error_reply(request)),
+        };
+
+        // Never answer a response: two servers could otherwise bounce replies back and forth forever
+        if query.header.query_indicator {
+            return None;
+        }
+
+        let mut response = self.respond(&query);
+
+        if let (Some(response_rate_limiter), Some(source)) = (&self.response_rate_limiter, source) {
+            // Identical responses share a bucket: same question and same rcode
+            let response_key = query.questions.first().map(QuestionSection::cache_key).unwrap_or_default();
+            if !response_rate_limiter.allow((source.ip(), response_key, response.header.response_code)) {
+                truncate(&mut response);
+            }
+        }
+
+        self.pad(&query, &mut response);
+        let mut serialized = response.serialize_to_bytes();
+
+        let limit = if over_udp { self.udp_limit(&query) } else { MAX_TCP_MESSAGE_LEN };
+        if serialized.len() > limit {
+            truncate(&mut response);
+            self.pad(&query, &mut response);
+            serialized = response.serialize_to_bytes();
+        }
+
+        // A reply with the wrong id is silently discarded by the client, so every path above must copy it over
+        debug_assert_eq!(response.header.id, query.header.id, "response id must match the query id");
+
+        Some(serialized)
+    }
+
+    /// Only pad for clients that padded their own query - RFC 7830 section 4
+    fn pad(&self, query: &DnsPacket, response: &mut DnsPacket) {
+
+        if let Some(block_size) = self.padding_block_size
+            && query.edns.as_ref().is_some_and(OptRecord::has_padding)
+        {
+            response.pad_to_block(block_size);
+        }
+    }
+
+    /// Largest UDP reply the client of `query` can take - RFC 6891 section 7
+    fn udp_limit(&self, query: &DnsPacket) -> usize {
+        match &query.edns {
+            Some(opt) => (opt.udp_payload_size.min(self.udp_payload_size) as usize).max(MIN_UDP_PAYLOAD_SIZE),
+            None => MIN_UDP_PAYLOAD_SIZE,
+        }
+    }
+
+    /// The complete response to a parsed query, with the server wide options applied. A query carrying an OPT
+    /// record gets one back (RFC 6891 section 7) advertising our own payload size, with DO echoed
+    fn respond(&self, query: &DnsPacket) -> DnsPacket {
+
+        let mut response = self.respond_without_edns(query);
+
+        // Z must be zero in every response (RFC 1035 section 4.1.1), whatever the query or an upstream set
+        response.header.reserved = false;
+
+        if let Some(query_opt) = &query.edns {
+            let mut opt = OptRecord::new();
+            opt.udp_payload_size = self.udp_payload_size;
+            opt.set_dnssec_ok(query_opt.dnssec_ok());
+            response.edns = Some(opt);
+        }
+
+        response
+    }
+
+    fn respond_without_edns(&self, query: &DnsPacket) -> DnsPacket {
+
+        if let Err(rcode) = validate_query(query).and_then(|()| self.check_type_supported(query)) {
+            let mut response = DnsPacket::error_response(query.header.id, query.questions.first().cloned(), rcode);
+            response.header.opcode = query.header.opcode;
+            return response;
+        }
+
+        let mut response = self.answer(query);
+
+        // Negative answers keep their authority section, it carries the proof of what does not exist
+        if self.minimal_responses && !response.answers.is_empty() {
+            response.authorities.clear();
+            response.additionals.clear();
+        }
+
+        if let Some(ttl) = self.force_ttl {
+            for record in response.answers.iter_mut().chain(&mut response.authorities).chain(&mut response.additionals) {
+                record.resource_record.ttl = ttl;
+            }
+        }
+
+        response
+    }
+
+    /// With reject_unknown_types set, NOTIMP for a question whose type this crate has no variant for.
+    /// AXFR is always NOTIMP here: transfers are only served over TCP by serve_tcp
+    fn check_type_supported(&self, query: &DnsPacket) -> Result<(), ResponseCode> {
+
+        if query.questions.iter().any(|question| RecordType::from(question.resource_record.record_type) == RecordType::Axfr) {
+            return Err(ResponseCode::NotImp);
+        }
+
+        let unknown = query.questions.iter()
+            .any(|question| matches!(RecordType::from(question.resource_record.record_type), RecordType::Unknown(_)));
+
+        if self.reject_unknown_types && unknown {
+            return Err(ResponseCode::NotImp);
+        }
+
+        Ok(())
+    }
+
+    /// Answer from the zone store when the name sits inside a zone we hold (one with an SOA at or above it) or the
+    /// store has the name anyway, otherwise forward upstream if a forwarder is configured. Misses inside our zones
+    /// are therefore answered with our own authoritative NXDOMAIN, never passed on
+    fn answer(&self, query: &DnsPacket) -> DnsPacket {
+
+        let Some(question) = query.questions.first() else {
+            return DnsPacket::error_response(query.header.id, None, ResponseCode::FormErr);
+        };
+
+        if DnsClass::from(question.resource_record.class) == DnsClass::Ch {
+            return self.answer_chaos(query, question);
+        }
+
+        if self.blocklist.contains(&question.resource_record.name) {
+            return self.answer_sinkhole(query, question);
+        }
+
+        if RecordType::from(question.resource_record.record_type) == RecordType::Ixfr {
+            return self.answer_ixfr(query, question);
+        }
+
+        let name = &question.resource_record.name;
+        let ours = self.zone.zone_soa(name).is_some() || self.zone.has_name(name);
+
+        match &self.forwarder {
+            Some(forwarder) if !ours => forward(query, question, forwarder),
+            _ => self.answer_from_zone(query, question),
+        }
+    }
+
+    /// Answer from our own data. Names we hold get an authoritative (AA) answer, anything else NXDOMAIN.
+    /// Positive answers carry the zone's NS records in the authority section, negative ones its SOA
+    fn answer_from_zone(&self, query: &DnsPacket, question: &QuestionSection) -> DnsPacket {
+
+        let mut response = DnsPacket::response_to(query);
+
+        let name = &question.resource_record.name;
+        let record_type = RecordType::from(question.resource_record.record_type);
+        let class = DnsClass::from(question.resource_record.class);
+
+        for record in self.chase_in_zone(name, record_type, class) {
+            response.answers.push(AnswerSection { resource_record: record });
+        }
+
+        if record_type != RecordType::Ns && !response.answers.is_empty() {
+            for nameserver in self.zone.zone_nameservers(name) {
+                response.authorities.push(AnswerSection { resource_record: nameserver });
+            }
+        }
+
+        // NXDOMAIN and NODATA carry the zone's SOA so resolvers know how long to cache the negative answer - RFC 2308.
+        // Holding the SOA makes us authoritative for the zone, so its NXDOMAINs are authoritative too
+        let mut in_our_zone = false;
+        if response.answers.is_empty() && let Some(mut soa) = self.zone.zone_soa(name) {
+            if let RData::Soa { minimum, .. } = soa.rdata {
+                soa.ttl = soa.ttl.min(minimum);
+            }
+            response.authorities.push(AnswerSection { resource_record: soa });
+            in_our_zone = true;
+        }
+
+        let name_exists = self.zone.has_name(name);
+        if !name_exists {
+            response.header.response_code = ResponseCode::NxDomain.into();
+        }
+        response.header.authoritative_answer = name_exists || in_our_zone;
+
+        response
+    }
+
+    /// The records answering `name`, following a CNAME in its place for as long as the target is also in our
+    /// data, so the client gets the whole chain and the final records in one response (RFC 1034 section 4.3.2).
+    /// A chain leaving our data ends at its last CNAME, for the client to chase elsewhere
+    fn chase_in_zone(&self, name: &str, record_type: RecordType, class: DnsClass) -> Vec<ResourceRecord> {
+
+        let mut answers = Vec::new();
+        let mut owner = name.to_string();
+        let mut visited = vec![owner.trim_end_matches('.').to_ascii_lowercase()];
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            let records = self.zone.lookup(&owner, record_type, class);
+            if !records.is_empty() || matches!(record_type, RecordType::Cname | RecordType::Any) {
+                answers.extend(records);
+                break;
+            }
+
+            let aliases = self.zone.lookup(&owner, RecordType::Cname, class);
+            let target = match aliases.first().map(|alias| &alias.rdata) {
+                Some(RData::Cname(target)) => target.clone(),
+                _ => break,
+            };
+            answers.extend(aliases);
+
+            // Stop at names outside our data and at loops
+            let target_key = target.trim_end_matches('.').to_ascii_lowercase();
+            if !self.zone.has_name(&target) || visited.contains(&target_key) {
+                break;
+            }
+            visited.push(target_key);
+            owner = target;
+        }
+
+        answers
+    }
+
+    /// A blocklisted name resolves to sinkhole_ip alone, whatever data we or the forwarder hold for it. Only the
+    /// question type matching the sinkhole's address family gets a record; other types get an empty answer
+    fn answer_sinkhole(&self, query: &DnsPacket, question: &QuestionSection) -> DnsPacket {
+
+        let mut response = DnsPacket::response_to(query);
+        response.header.recursion_available = self.forwarder.is_some();
+
+        let name = &question.resource_record.name;
+        let rdata = match (RecordType::from(question.resource_record.record_type), self.sinkhole_ip) {
+            (RecordType::A, IpAddr::V4(address)) => Some(RData::A(address)),
+            (RecordType::Aaaa, IpAddr::V6(address)) => Some(RData::Aaaa(address)),
+            _ => None,
+        };
+        response.answers.extend(rdata.map(|rdata| AnswerSection::from_rdata(name, SINKHOLE_TTL, rdata)));
+
+        response
+    }
+
+    /// IXFR outside serve_tcp gets the zone's current SOA alone (RFC 1995 section 2), which tells a client that is
+    /// behind to ask again over TCP for the transfer itself
+    fn answer_ixfr(&self, query: &DnsPacket, question: &QuestionSection) -> DnsPacket {
+
+        match self.transfer_soa(question) {
+            Ok(soa) => {
+                let mut response = DnsPacket::response_to(query);
+                response.header.authoritative_answer = true;
+                response.answers.push(AnswerSection { resource_record: soa });
+                response
+            }
+            Err(rcode) => DnsPacket::error_response(query.header.id, Some(question.clone()), rcode),
+        }
+    }
+
+    /// CHAOS class queries are server diagnostics rather than data: only the version TXT record is served,
+    /// everything else in the class is refused
+    fn answer_chaos(&self, query: &DnsPacket, question: &QuestionSection) -> DnsPacket {
+
+        let mut response = DnsPacket::response_to(query);
+
+        let name = question.resource_record.name.trim_end_matches('.').to_ascii_lowercase();
+        let record_type = RecordType::from(question.resource_record.record_type);
+
+        match &self.version {
+            Some(version) if record_type == RecordType::Txt && (name == "version.bind" || name == "version.server") => {
+                let mut answer = AnswerSection::from_rdata(&question.resource_record.name, 0, RData::Txt(vec![version.clone()]));
+                answer.resource_record.class = DnsClass::Ch.into();
+
+                response.header.authoritative_answer = true;
+                response.answers.push(answer);
+            }
+            _ => response.header.response_code = ResponseCode::Refused.into(),
+        }
+
+        response
+    }
+}
+
+/// The server loop for async callers. Spawn it on any executor (tokio::spawn included) in place of a pool of
+/// threads blocking on the socket
+#[cfg(feature = "async")]
+impl Server {
+    /// Answer datagrams arriving on `socket` until receiving fails, returning that error. Each receive is awaited
+    /// through nonblocking::unblock, and each query is answered on a thread of its own that the loop does not
+    /// wait for, so a slow upstream forward holds up that one query and nothing else. Rate limits apply as in
+    /// handle_query_from
+    pub async fn serve_udp_async(self: Arc<Self>, socket: UdpSocket) -> io::Result<()> {
+
+        let socket = Arc::new(socket);
+        let recv_buffer_len = usize::from(self.udp_payload_size).max(MIN_UDP_PAYLOAD_SIZE);
+
+        loop {
+            let receiver = Arc::clone(&socket);
+            let (datagram, source_address) = unblock(move || {
+                let mut recv_buffer = vec![0; recv_buffer_len];
+                let (number_of_bytes, source_address) = receiver.recv_from(&mut recv_buffer)?;
+                recv_buffer.truncate(number_of_bytes);
+                Ok::<_, io::Error>((recv_buffer, source_address))
+            }).await?;
+
+            let (server, sender) = (Arc::clone(&self), Arc::clone(&socket));
+            thread::spawn(move || {
+                if let Some(reply) = server.handle_query_from(&datagram, source_address) {
+                    let _ = sender.send_to(&reply, source_address);
+                }
+            });
+        }
+    }
+}
+
+
+/// Relay the question upstream and copy the upstream's answer into our response. The upstream's AA flag and rcode
+/// are passed through untouched - we are not authoritative for data we merely relay. Upstream failure is SERVFAIL
+fn forward(query: &DnsPacket, question: &QuestionSection, forwarder: &Resolver) -> DnsPacket {
+
+    let mut response = DnsPacket::response_to(query);
+    response.header.recursion_available = true;
+
+    // The client's own question bytes go upstream, so the name keeps the letter case it was asked with
+    let upstream_result = forwarder.query_question(question.clone());
+
+    match upstream_result {
+        Ok(upstream) => {
+            response.header.authoritative_answer = upstream.header.authoritative_answer;
+            response.header.response_code = upstream.header.response_code;
+            response.answers = upstream.answers;
+            response.authorities = upstream.authorities;
+            response.additionals = upstream.additionals;
+        }
+        Err(_) => response.header.response_code = ResponseCode::ServFail.into(),
+    }
+
+    response
+}
+
+
+/// The serial number of an SOA record, None for any other record
+fn soa_serial(record: &ResourceRecord) -> Option<u32> {
+    match record.rdata {
+        RData::Soa { serial, .. } => Some(serial),
+        _ => None,
+    }
+}
+
+/// True when serial `a` is equal to or newer than `b` in serial number arithmetic, which lets serials wrap - RFC 1982
+fn serial_at_least(a: u32, b: u32) -> bool {
+    a.wrapping_sub(b) as i32 >= 0
+}
+
+
+/// Empty a response down to its header and question with TC set, telling the client to ask again over TCP
+fn truncate(response: &mut DnsPacket) {
+    response.header.truncation = true;
+    response.answers.clear();
+    response.authorities.clear();
+    response.additionals.clear();
+}
+
+
+/// Check that a packet is something we can answer: a query (QR clear) using the standard QUERY opcode and holding
+/// at least one question. The error is the rcode to reply with - NOTIMP for other opcodes, FORMERR otherwise
+pub fn validate_query(packet: &DnsPacket) -> Result<(), ResponseCode> {
+
+    if packet.header.query_indicator {
+        return Err(ResponseCode::FormErr);
+    }
+    if Opcode::from(packet.header.opcode) != Opcode::Query {
+        return Err(ResponseCode::NotImp);
+    }
+    if packet.questions.is_empty() {
+        return Err(ResponseCode::FormErr);
+    }
+
+    Ok(())
+}
+
+
+/// FORMERR reply for a datagram that could not be parsed, echoing whatever transaction id could be read from it
+fn format_error_reply(request: &[u8]) -> Vec<u8> {
+
+    let query_id = u16::from_be_bytes([request[0], request[1]]);
+
+    DnsPacket::error_response(query_id, None, ResponseCode::FormErr).serialize_to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::mock_udp_server;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    fn example_zone() -> ZoneStore {
+        let mut zone = ZoneStore::new();
+        zone.insert(AnswerSection::from_rdata("www.example.com", 300, RData::A(Ipv4Addr::new(192, 0, 2, 1))).resource_record);
+        zone
+    }
+
+    /// Send `query` through handle_query and parse the reply
+    fn exchange(server: &Server, query: &DnsPacket) -> DnsPacket {
+        DnsPacket::parse(&server.handle_query(&query.serialize_to_bytes()).unwrap()).unwrap()
+    }
+
+    fn ask(server: &Server, name: &str, record_type: RecordType) -> DnsPacket {
+        exchange(server, &DnsPacket::query_multi(0x2222, vec![(name.to_string(), record_type)]))
+    }
+
+    #[test]
+    fn short_datagram_gets_formerr_with_its_id() {
+        let server = Server::new(ZoneStore::new());
+
+        let reply = server.handle_query(&[0xAB, 0xCD, 0x01, 0x00, 0x00]).unwrap();
+        let reply = DnsPacket::parse(&reply).unwrap();
+
+        assert_eq!(reply.header.id, 0xABCD);
+        assert!(reply.header.query_indicator);
+        assert_eq!(ResponseCode::from(reply.header.response_code), ResponseCode::FormErr);
+
+        assert!(server.handle_query(&[0xAB]).is_none());
+    }
+
+    #[test]
+    fn zone_answers_are_authoritative_and_forwarded_ones_keep_the_upstream_flag() {
+        let upstream = mock_udp_server(|query| {
+            let mut response = DnsPacket::answer_with(query, vec![RData::A(Ipv4Addr::new(198, 51, 100, 1))], 300);
+            response.header.authoritative_answer = query.questions[0].resource_record.name == "www.example.org";
+            Some(response)
+        });
+
+        let mut server = Server::new(example_zone());
+        server.forwarder = Some(Resolver::new(upstream));
+
+        assert!(ask(&server, "www.example.com", RecordType::A).header.authoritative_answer);
+
+        let forwarded = ask(&server, "www.example.org", RecordType::A);
+        assert!(forwarded.header.authoritative_answer);
+        assert!(forwarded.header.recursion_available);
+        assert_eq!(forwarded.answers.len(), 1);
+
+        // A non-authoritative upstream answer is not promoted either
+        assert!(!ask(&server, "www.example.net", RecordType::A).header.authoritative_answer);
+    }
+
+    #[test]
+    fn version_bind_returns_the_configured_string() {
+        let mut server = Server::new(ZoneStore::new());
+        server.version = Some("dns_r 0.1.0".to_string());
+
+        let mut query = DnsPacket::new();
+        query.questions.push(QuestionSection::new_query("version.bind", RecordType::Txt, DnsClass::Ch));
+
+        let response = exchange(&server, &query);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(DnsClass::from(response.answers[0].resource_record.class), DnsClass::Ch);
+        assert_eq!(response.answers[0].rdata().unwrap(), RData::Txt(vec!["dns_r 0.1.0".to_string()]));
+
+        server.version = None;
+        assert_eq!(ResponseCode::from(exchange(&server, &query).header.response_code), ResponseCode::Refused);
+    }
+
+    #[test]
+    fn ns_query_returns_the_zone_nameservers() {
+        let mut zone = example_zone();
+        for nameserver in ["ns1.example.com", "ns2.example.com"] {
+            zone.insert(AnswerSection::from_rdata("example.com", 3600, RData::Ns(nameserver.to_string())).resource_record);
+        }
+        let server = Server::new(zone);
+
+        let response = ask(&server, "example.com", RecordType::Ns);
+
+        let nameservers: Vec<RData> = response.answers.iter().map(|answer| answer.rdata().unwrap()).collect();
+        assert_eq!(nameservers, vec![RData::Ns("ns1.example.com".to_string()), RData::Ns("ns2.example.com".to_string())]);
+        assert!(response.authorities.is_empty());
+        assert!(response.header.authoritative_answer);
+
+        // Other answers in the zone carry the same NS records as their authority section
+        assert_eq!(ask(&server, "www.example.com", RecordType::A).authorities.len(), 2);
+    }
+
+    #[test]
+    fn force_ttl_overrides_every_record() {
+        let mut zone = example_zone();
+        zone.insert(AnswerSection::from_rdata("example.com", 3600, RData::Ns("ns1.example.com".to_string())).resource_record);
+        let mut server = Server::new(zone);
+        server.force_ttl = Some(10);
+
+        let response = ask(&server, "www.example.com", RecordType::A);
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.authorities.len(), 1);
+        assert!(response.answers.iter().chain(&response.authorities).all(|record| record.resource_record.ttl == 10));
+    }
+
+    #[test]
+    fn minimal_responses_leave_out_authority_and_additional() {
+        let mut zone = example_zone();
+        zone.insert(AnswerSection::from_rdata("example.com", 3600, RData::Ns("ns1.example.com".to_string())).resource_record);
+        let mut server = Server::new(zone);
+        server.minimal_responses = true;
+
+        let response = ask(&server, "www.example.com", RecordType::A);
+
+        assert_eq!(response.answers.len(), 1);
+        assert!(response.authorities.is_empty());
+        assert!(response.additionals.is_empty());
+    }
+
+    #[test]
+    fn validate_query_rejection_paths() {
+        let query = DnsPacket::query_multi(1, vec![("example.com".to_string(), RecordType::A)]);
+        assert_eq!(validate_query(&query), Ok(()));
+
+        let mut response = query.clone();
+        response.header.query_indicator = true;
+        assert_eq!(validate_query(&response), Err(ResponseCode::FormErr));
+
+        let mut status = query.clone();
+        status.header.opcode = Opcode::Status.into();
+        assert_eq!(validate_query(&status), Err(ResponseCode::NotImp));
+
+        let mut no_question = query.clone();
+        no_question.questions.clear();
+        assert_eq!(validate_query(&no_question), Err(ResponseCode::FormErr));
+    }
+
+    #[test]
+    fn reply_echoes_the_query_id() {
+        let server = Server::new(example_zone());
+
+        let mut query = DnsPacket::query_multi(0xABCD, vec![("www.example.com".to_string(), RecordType::A)]);
+        assert_eq!(exchange(&server, &query).header.id, 0xABCD);
+
+        // Error replies too
+        query.header.opcode = Opcode::Status.into();
+        let error = exchange(&server, &query);
+        assert_eq!(error.header.id, 0xABCD);
+        assert_eq!(ResponseCode::from(error.header.response_code), ResponseCode::NotImp);
+    }
+
+    #[test]
+    fn mixed_case_question_is_echoed_byte_for_byte() {
+        let server = Server::new(example_zone());
+        let request = DnsPacket::query_multi(0x3030, vec![("wWw.ExAmPlE.cOm".to_string(), RecordType::A)]).serialize_to_bytes();
+
+        let reply = server.handle_query(&request).unwrap();
+
+        let question_end = DnsHeader::DNS_HEADER_LEN + 17 + 4;
+        assert_eq!(reply[DnsHeader::DNS_HEADER_LEN..question_end], request[DnsHeader::DNS_HEADER_LEN..question_end]);
+        assert_eq!(DnsPacket::parse(&reply).unwrap().answers.len(), 1);
+    }
+
+    fn example_soa(serial: u32) -> RData {
+        RData::Soa {
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1_209_600,
+            minimum: 300,
+        }
+    }
+
+    #[test]
+    fn nxdomain_carries_exactly_one_soa() {
+        let mut zone = example_zone();
+        zone.insert(AnswerSection::from_rdata("example.com", 3600, example_soa(1)).resource_record);
+        let server = Server::new(zone);
+
+        let response = ask(&server, "missing.example.com", RecordType::A);
+
+        assert_eq!(ResponseCode::from(response.header.response_code), ResponseCode::NxDomain);
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.authorities.len(), 1);
+        assert_eq!(RecordType::from(response.authorities[0].resource_record.record_type), RecordType::Soa);
+        assert_eq!(response.authorities[0].resource_record.ttl, 300);
+    }
+
+    #[test]
+    fn queries_over_the_source_rate_are_dropped() {
+        let mut server = Server::new(example_zone());
+        server.rate_limiter = Some(RateLimiter::new(1, 5));
+
+        let request = DnsPacket::query_multi(1, vec![("www.example.com".to_string(), RecordType::A)]).serialize_to_bytes();
+        let source: SocketAddr = "192.0.2.10:5353".parse().unwrap();
+
+        assert!((0..5).all(|_| server.handle_query_from(&request, source).is_some()));
+        assert!(server.handle_query_from(&request, source).is_none());
+        assert!(server.handle_query_from(&request, "192.0.2.11:5353".parse().unwrap()).is_some());
+    }
+
+    #[test]
+    fn repeated_identical_responses_are_truncated() {
+        let mut server = Server::new(example_zone());
+        server.response_rate_limiter = Some(RateLimiter::new(1, 2));
+
+        let request = DnsPacket::query_multi(1, vec![("www.example.com".to_string(), RecordType::A)]).serialize_to_bytes();
+        let source: SocketAddr = "192.0.2.10:5353".parse().unwrap();
+        let reply = || DnsPacket::parse(&server.handle_query_from(&request, source).unwrap()).unwrap();
+
+        assert!((0..2).all(|_| !reply().is_truncated()));
+
+        let limited = reply();
+        assert!(limited.is_truncated());
+        assert!(limited.answers.is_empty());
+        assert_eq!(limited.questions.len(), 1);
+    }
+
+    #[test]
+    fn edns_query_gets_an_opt_record_back() {
+        let mut server = Server::new(example_zone());
+        server.udp_payload_size = 1400;
+
+        let plain = ask(&server, "www.example.com", RecordType::A);
+        assert!(plain.edns.is_none());
+
+        let mut query = DnsPacket::query_multi(0x4444, vec![("www.example.com".to_string(), RecordType::A)]);
+        let mut opt = OptRecord::new();
+        opt.udp_payload_size = 4096;
+        opt.set_dnssec_ok(true);
+        query.edns = Some(opt);
+
+        let response = exchange(&server, &query);
+        let response_opt = response.edns.expect("EDNS query should get an OPT record back");
+        assert_eq!(response_opt.udp_payload_size, 1400);
+        assert!(response_opt.dnssec_ok());
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    /// One side of a TCP connection: reads come from what the client sent, writes are collected for inspection
+    struct ScriptedStream {
+        sent_by_client: Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl ScriptedStream {
+        fn new(queries: &[DnsPacket]) -> ScriptedStream {
+            let sent_by_client = queries.iter().flat_map(|query| query.serialize_to_tcp_bytes().unwrap()).collect();
+            ScriptedStream { sent_by_client: Cursor::new(sent_by_client), written: Vec::new() }
+        }
+    }
+
+    impl Read for ScriptedStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sent_by_client.read(buf)
+        }
+    }
+
+    impl Write for ScriptedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn serve_tcp_answers_every_framed_query_until_the_client_closes() {
+        let server = Server::new(example_zone());
+        let queries = [
+            DnsPacket::query_multi(1, vec![("www.example.com".to_string(), RecordType::A)]),
+            DnsPacket::query_multi(2, vec![("missing.example.com".to_string(), RecordType::A)]),
+        ];
+        let mut stream = ScriptedStream::new(&queries);
+
+        server.serve_tcp(&mut stream).unwrap();
+
+        let mut replies = Cursor::new(stream.written);
+        let first = DnsPacket::parse_from_reader(&mut replies).unwrap();
+        assert_eq!(first.header.id, 1);
+        assert_eq!(first.answers.len(), 1);
+
+        let second = DnsPacket::parse_from_reader(&mut replies).unwrap();
+        assert_eq!(second.header.id, 2);
+        assert_eq!(ResponseCode::from(second.header.response_code), ResponseCode::NxDomain);
+        assert_eq!(replies.position() as usize, replies.get_ref().len());
+
+        // A connection closed part way through a message is an error, not a clean close
+        let mut cut_short = ScriptedStream::new(&queries[..1]);
+        cut_short.sent_by_client.get_mut().pop();
+        assert!(server.serve_tcp(&mut cut_short).is_err());
+    }
+
+    #[test]
+    fn forwarded_question_keeps_the_clients_bytes() {
+        let asked_upstream = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&asked_upstream);
+        let upstream = mock_udp_server(move |query| {
+            recorder.lock().unwrap().push(query.questions[0].clone());
+            Some(DnsPacket::answer_with(query, vec![RData::A(Ipv4Addr::new(198, 51, 100, 1))], 300))
+        });
+
+        let mut server = Server::new(example_zone());
+        server.forwarder = Some(Resolver::new(upstream));
+
+        let mut query = DnsPacket::new();
+        query.header.id = 0x5151;
+        query.questions.push(QuestionSection::new_query("WwW.ExAmPlE.oRg", RecordType::A, DnsClass::Hs));
+        let request = query.serialize_to_bytes();
+        let reply = server.handle_query(&request).unwrap();
+
+        let question_end = DnsHeader::DNS_HEADER_LEN + 17 + 4;
+        let asked = asked_upstream.lock().unwrap().pop().unwrap();
+        assert_eq!(asked.wire_name.as_deref(), Some(&request[DnsHeader::DNS_HEADER_LEN..question_end - 4]));
+        assert_eq!(DnsClass::from(asked.resource_record.class), DnsClass::Hs);
+        assert_eq!(reply[DnsHeader::DNS_HEADER_LEN..question_end], request[DnsHeader::DNS_HEADER_LEN..question_end]);
+
+        let reply = DnsPacket::parse(&reply).unwrap();
+        assert_eq!(reply.header.id, 0x5151);
+        assert_eq!(reply.answers[0].as_ipv4(), Some(Ipv4Addr::new(198, 51, 100, 1)));
+    }
+
+    #[test]
+    fn cname_answer_includes_the_targets_records() {
+        let mut zone = example_zone();
+        zone.insert(AnswerSection::from_rdata("alias.example.com", 300, RData::Cname("www.example.com".to_string())).resource_record);
+        let server = Server::new(zone);
+
+        let response = ask(&server, "alias.example.com", RecordType::A);
+
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[0].rdata().unwrap(), RData::Cname("www.example.com".to_string()));
+        assert_eq!(response.answers[1].resource_record.name, "www.example.com");
+        assert_eq!(response.answers[1].as_ipv4(), Some(Ipv4Addr::new(192, 0, 2, 1)));
+        assert!(response.header.authoritative_answer);
+
+        // Asking for the CNAME itself does not chase it
+        assert_eq!(ask(&server, "alias.example.com", RecordType::Cname).answers.len(), 1);
+    }
+
+    #[test]
+    fn unknown_question_type_gets_notimp_when_rejected() {
+        let mut server = Server::new(example_zone());
+        let unknown = RecordType::Unknown(65280);
+
+        let answered = ask(&server, "www.example.com", unknown);
+        assert_eq!(ResponseCode::from(answered.header.response_code), ResponseCode::NoError);
+        assert!(answered.answers.is_empty());
+
+        server.reject_unknown_types = true;
+        let rejected = ask(&server, "www.example.com", unknown);
+        assert_eq!(ResponseCode::from(rejected.header.response_code), ResponseCode::NotImp);
+        assert_eq!(rejected.questions.len(), 1);
+
+        assert_eq!(ask(&server, "www.example.com", RecordType::A).answers.len(), 1);
+    }
+
+    /// Send `query` to serve_tcp and collect every message written back
+    fn exchange_tcp(server: &Server, query: &DnsPacket) -> Vec<DnsPacket> {
+        let mut stream = ScriptedStream::new(std::slice::from_ref(query));
+        server.serve_tcp(&mut stream).unwrap();
+
+        let mut replies = Cursor::new(stream.written);
+        let mut messages = Vec::new();
+        while (replies.position() as usize) < replies.get_ref().len() {
+            messages.push(DnsPacket::parse_from_reader(&mut replies).unwrap());
+        }
+        messages
+    }
+
+    fn transfer_zone(serial: u32) -> ZoneStore {
+        let mut zone = example_zone();
+        zone.insert(AnswerSection::from_rdata("example.com", 3600, example_soa(serial)).resource_record);
+        zone.insert(AnswerSection::from_rdata("example.com", 3600, RData::Ns("ns1.example.com".to_string())).resource_record);
+        zone
+    }
+
+    #[test]
+    fn axfr_over_tcp_sends_the_zone_between_two_soas() {
+        let mut server = Server::new(transfer_zone(7));
+        let query = DnsPacket::query_multi(0x0AF0, vec![("example.com".to_string(), RecordType::Axfr)]);
+
+        let refused = exchange_tcp(&server, &query);
+        assert_eq!(refused.len(), 1);
+        assert_eq!(ResponseCode::from(refused[0].header.response_code), ResponseCode::Refused);
+
+        server.allow_transfer = true;
+        let messages = exchange_tcp(&server, &query);
+        let records: Vec<&AnswerSection> = messages.iter().flat_map(|message| &message.answers).collect();
+
+        assert!(messages.iter().all(|message| message.header.id == 0x0AF0 && message.header.authoritative_answer));
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].rdata().unwrap(), example_soa(7));
+        assert_eq!(records[3].rdata().unwrap(), example_soa(7));
+        let middle: Vec<RecordType> = records[1..3].iter().map(|record| RecordType::from(record.resource_record.record_type)).collect();
+        assert_eq!(middle, vec![RecordType::Ns, RecordType::A]);
+
+        // Over UDP an AXFR is never answered with the zone
+        assert_eq!(ResponseCode::from(exchange(&server, &query).header.response_code), ResponseCode::NotImp);
+    }
+
+    #[test]
+    fn ixfr_from_an_old_serial_gets_a_full_transfer() {
+        let mut server = Server::new(transfer_zone(7));
+        server.allow_transfer = true;
+        let ixfr_from = |serial| {
+            let mut query = DnsPacket::query_multi(0x1F0, vec![("example.com".to_string(), RecordType::Ixfr)]);
+            query.authorities.push(AnswerSection::from_rdata("example.com", 3600, example_soa(serial)));
+            query
+        };
+
+        let messages = exchange_tcp(&server, &ixfr_from(5));
+        let records: Vec<&AnswerSection> = messages.iter().flat_map(|message| &message.answers).collect();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].rdata().unwrap(), example_soa(7));
+        assert_eq!(records[3].rdata().unwrap(), example_soa(7));
+
+        // Already current: the SOA alone
+        let current = exchange_tcp(&server, &ixfr_from(7));
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].answers.len(), 1);
+        assert_eq!(current[0].answers[0].rdata().unwrap(), example_soa(7));
+    }
+
+    #[test]
+    fn blocklisted_name_resolves_to_the_sinkhole() {
+        let mut server = Server::new(example_zone());
+        server.blocklist.insert("www.example.com");
+        server.sinkhole_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+        let response = ask(&server, "www.example.com", RecordType::A);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.answers[0].as_ipv4(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(response.answers[0].resource_record.ttl, SINKHOLE_TTL);
+
+        // The sinkhole is IPv4, so AAAA questions get an empty answer
+        assert!(ask(&server, "www.example.com", RecordType::Aaaa).answers.is_empty());
+    }
+
+    #[test]
+    fn udp_replies_are_truncated_past_the_clients_payload_size() {
+        let mut zone = ZoneStore::new();
+        for index in 0..5 {
+            zone.insert(AnswerSection::txt_record("big.example.com", 300, &format!("{}{}", index, "x".repeat(199))).resource_record);
+        }
+        let server = Server::new(zone);
+        let query_with_payload_size = |udp_payload_size| {
+            let mut query = DnsPacket::query_multi(0x1000, vec![("big.example.com".to_string(), RecordType::Txt)]);
+            let mut opt = OptRecord::new();
+            opt.udp_payload_size = udp_payload_size;
+            query.edns = Some(opt);
+            query.serialize_to_bytes()
+        };
+
+        let full = server.handle_query(&query_with_payload_size(4096)).unwrap();
+        assert!(full.len() > 1000);
+        let full = DnsPacket::parse(&full).unwrap();
+        assert!(!full.is_truncated());
+        assert_eq!(full.answers.len(), 5);
+
+        let small = server.handle_query(&query_with_payload_size(512)).unwrap();
+        assert!(small.len() <= 512);
+        let small = DnsPacket::parse(&small).unwrap();
+        assert!(small.is_truncated());
+        assert!(small.answers.is_empty());
+    }
+
+    #[test]
+    fn z_bit_is_clear_in_every_response() {
+        let server = Server::new(example_zone());
+        let mut query = DnsPacket::query_multi(1, vec![("www.example.com".to_string(), RecordType::A)]);
+        assert!(!query.header.reserved);
+
+        query.header.reserved = true;
+        let reply = server.handle_query(&query.serialize_to_bytes()).unwrap();
+
+        assert_eq!(reply[3] & 0x40, 0);
+        assert!(!DnsPacket::parse(&reply).unwrap().header.reserved);
+    }
+
+    #[test]
+    fn tcp_reply_too_long_to_frame_is_truncated() {
+        let mut zone = ZoneStore::new();
+        for index in 0..300 {
+            zone.insert(AnswerSection::txt_record("huge.example.com", 300, &format!("{:03}{}", index, "x".repeat(252))).resource_record);
+        }
+        let server = Server::new(zone);
+
+        let replies = exchange_tcp(&server, &DnsPacket::query_multi(1, vec![("huge.example.com".to_string(), RecordType::Txt)]));
+
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].is_truncated());
+        assert!(replies[0].answers.is_empty());
+    }
+
+    #[test]
+    fn misses_inside_our_zone_are_not_forwarded() {
+        let upstream = mock_udp_server(|query| Some(DnsPacket::answer_with(query, vec![RData::A(Ipv4Addr::new(198, 51, 100, 1))], 300)));
+        let mut zone = example_zone();
+        zone.insert(AnswerSection::from_rdata("example.com", 3600, example_soa(1)).resource_record);
+        let mut server = Server::new(zone);
+        server.forwarder = Some(Resolver::new(upstream));
+
+        let response = ask(&server, "missing.example.com", RecordType::A);
+
+        assert_eq!(ResponseCode::from(response.header.response_code), ResponseCode::NxDomain);
+        assert!(response.header.authoritative_answer);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities[0].rdata().unwrap(), example_soa(1));
+
+        assert_eq!(ask(&server, "www.example.org", RecordType::A).answers.len(), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_udp_loop_answers_queries() {
+        use crate::test_support::block_on;
+        use std::net::UdpSocket;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let server = Arc::new(Server::new(example_zone()));
+        std::thread::spawn(move || block_on(server.serve_udp_async(socket)));
+
+        let resolver = Arc::new(Resolver::new(address));
+        let addresses = block_on(resolver.resolve_host_async("www.example.com")).unwrap();
+
+        assert_eq!(addresses, vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+    }
+
+    #[test]
+    fn resolver_transfers_a_zone_from_serve_tcp() {
+        let mut zone = transfer_zone(7);
+        for host in 0..1000u16 {
+            let address = Ipv4Addr::new(192, 0, (host >> 8) as u8, host as u8);
+            zone.insert(AnswerSection::from_rdata(&format!("host{}.example.com", host), 300, RData::A(address)).resource_record);
+        }
+        let mut server = Server::new(zone);
+        server.allow_transfer = true;
+        let server = Arc::new(server);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let serving = Arc::clone(&server);
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = serving.serve_tcp(&mut stream);
+            }
+        });
+
+        let records = Resolver::new(address).transfer_zone("example.com").unwrap();
+
+        // The SOA once, then every other record - spread over more than one message, as 1000 hosts need
+        assert_eq!(records.len(), 1 + 1 + 1 + 1000);
+        assert_eq!(AnswerSection { resource_record: records[0].clone() }.rdata().unwrap(), example_soa(7));
+        assert!(records[1..].iter().all(|record| RecordType::from(record.record_type) != RecordType::Soa));
+        assert!(records.iter().any(|record| record.name == "host999.example.com"));
+
+        let unknown = Resolver::new(address).transfer_zone("example.org");
+        assert!(matches!(unknown, Err(DnsError::TransferRefused(rcode)) if ResponseCode::from(rcode) == ResponseCode::NotAuth));
+    }
+}
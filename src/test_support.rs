@@ -0,0 +1,92 @@
+/*
+*   Purpose: Mock DNS servers on the loopback interface for the unit tests of the networked modules
+*   Author: Mauzy0x00
+*/
+
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+#[cfg(feature = "async")]
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+
+use crate::dns::DnsPacket;
+
+
+/// Start a UDP server on an unused loopback port that answers each query with `respond(query)`, sending nothing
+/// back when it returns None. Datagrams that do not parse are ignored. The server runs until the test process exits
+pub(crate) fn mock_udp_server(respond: impl Fn(&DnsPacket) -> Option<DnsPacket> + Send + 'static) -> SocketAddr {
+    mock_udp_server_at("127.0.0.1:0".parse().unwrap(), respond)
+}
+
+/// mock_udp_server bound to a given address
+pub(crate) fn mock_udp_server_at(address: SocketAddr, respond: impl Fn(&DnsPacket) -> Option<DnsPacket> + Send + 'static) -> SocketAddr {
+
+    let socket = UdpSocket::bind(address).expect("bind mock UDP server");
+    let address = socket.local_addr().expect("mock UDP server address");
+
+    thread::spawn(move || {
+        let mut recv_buffer = [0; 4096];
+        loop {
+            let Ok((number_of_bytes, source_address)) = socket.recv_from(&mut recv_buffer) else {
+                return;
+            };
+            let Ok(query) = DnsPacket::parse(&recv_buffer[..number_of_bytes]) else {
+                continue;
+            };
+            if let Some(response) = respond(&query) {
+                let _ = socket.send_to(&response.serialize_to_bytes(), source_address);
+            }
+        }
+    });
+
+    address
+}
+
+/// The TCP counterpart of mock_udp_server_at: connections are served one after another, each answering
+/// framed queries until the client closes it. Pass the address of a UDP mock to serve both on one port
+pub(crate) fn mock_tcp_server_at(address: SocketAddr, respond: impl Fn(&DnsPacket) -> Option<DnsPacket> + Send + 'static) -> SocketAddr {
+
+    let listener = TcpListener::bind(address).expect("bind mock TCP server");
+    let address = listener.local_addr().expect("mock TCP server address");
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            while let Ok(query) = DnsPacket::parse_from_reader(&mut stream) {
+                if let Some(response) = respond(&query)
+                    && response.write_to_writer(&mut stream).is_err()
+                {
+                    break;
+                }
+            }
+        }
+    });
+
+    address
+}
+
+/// Drive a future to completion on the calling thread, parking between polls - a stand-in for a runtime's block_on
+#[cfg(feature = "async")]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::dns::{DnsClass, RecordType, ResourceRecord};
+use crate::error::DnsError;
+use crate::rdata::RData;
+
+
+const DEFAULT_KEYSET_TTL: u32 = 3600;       // TTL for keyset lines that don't give one
+
+
+/// In-memory store of the records this server answers for, keyed by owner name and record type.
+/// Records within an RRset come back in the order they were inserted, lookup after lookup, so answers are
+/// reproducible and never shuffled behind a signer's back (RFC 2181 section 5). round_robin is the only exception
+pub struct ZoneStore {
+    records: HashMap<(String, RecordType), Vec<ResourceRecord>>,
+    names: HashSet<String>,         // Every name that exists: owners plus all their ancestors, so empty non-terminals count too
+    pub round_robin: bool,          // When set, multi-record answers are rotated on every lookup to spread load across the values. Off by default
+    rotation: AtomicUsize,          // Internal counter deciding which record leads the next rotated answer
+}
+
+impl ZoneStore {
+    pub fn new() -> ZoneStore {
+        ZoneStore {
+            records: HashMap::new(),
+            names: HashSet::new(),
+            round_robin: false,
+            rotation: AtomicUsize::new(0),
+        }
+    }
+
+    /// Add a record to the store. Records sharing a name and type are kept in insertion order
+    pub fn insert(&mut self, record: ResourceRecord) {
+        let key = (normalize_name(&record.name), RecordType::from(record.record_type));
+
+        let mut name = key.0.as_str();
+        while self.names.insert(name.to_string()) {     // Stops at the first name already known, whose ancestors are known too
+            let Some((_, parent)) = name.split_once('.') else {
+                break;
+            };
+            name = parent;
+        }
+
+        self.records.entry(key).or_default().push(record);
+    }
+
+    /// Load pre-generated DNSSEC key material from a file in zone file syntax, one record per line, as written by
+    /// dnssec-keygen (`.key` files) and dnssec-dsfromkey: `<owner> [<ttl>] [IN] DNSKEY <flags> <protocol> <algorithm> <base64 key>`
+    /// or `<owner> [<ttl>] [IN] DS <key tag> <algorithm> <digest type> <hex digest>`. Blank lines and `;` comments
+    /// are skipped. The records are stored as opaque rdata and served as-is - nothing is signed or verified here.
+    /// Returns how many records were added
+    pub fn load_keyset(&mut self, path: impl AsRef<Path>) -> Result<usize, DnsError> {
+
+        let text = fs::read_to_string(path)?;
+        let mut loaded = 0;
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            self.insert(parse_keyset_line(line)?);
+            loaded += 1;
+        }
+
+        Ok(loaded)
+    }
+
+    /// Return every record stored for the name and type in the given class. When the name does not exist at all, a
+    /// wildcard (`*.<closest encloser>`) stands in for it and its records are returned renamed to the queried name.
+    /// The two ANY values are independent: RecordType::Any returns the records of every type at the name, while
+    /// DnsClass::Any matches records of any class. With round_robin enabled the list is rotated by one more position
+    /// on each call, so consecutive lookups lead with a different record
+    pub fn lookup(&self, name: &str, record_type: RecordType, class: DnsClass) -> Vec<ResourceRecord> {
+
+        let normalized = normalize_name(name);
+
+        let mut found = if self.name_exists(&normalized) {
+            self.records_at(&normalized, record_type)
+        } else {
+            self.synthesize_from_wildcard(name, &normalized, record_type)
+        };
+
+        found.retain(|record| class == DnsClass::Any || DnsClass::from(record.class) == class);
+
+        // An ANY answer mixes record types, so there is no single RRset to rotate
+        if self.round_robin && record_type != RecordType::Any && found.len() > 1 {
+            let turn = self.rotation.fetch_add(1, Ordering::Relaxed);
+            let found_len = found.len();
+            found.rotate_left(turn % found_len);
+        }
+
+        found
+    }
+
+    /// True when the name exists: it owns records, has names below it (an empty non-terminal), or is covered by a
+    /// wildcard. This is the difference between NODATA and NXDOMAIN
+    pub fn has_name(&self, name: &str) -> bool {
+        let name = normalize_name(name);
+        self.name_exists(&name) || self.wildcard_for(&name).is_some()
+    }
+
+    /// NS records of the zone holding `name`: those of the closest ancestor (or the name itself) that has any.
+    /// Empty when no enclosing name in the store has NS records
+    pub fn zone_nameservers(&self, name: &str) -> Vec<ResourceRecord> {
+        self.closest_enclosing(name, RecordType::Ns).cloned().unwrap_or_default()
+    }
+
+    /// SOA record of the zone holding `name`, found the same way as zone_nameservers
+    pub fn zone_soa(&self, name: &str) -> Option<ResourceRecord> {
+        self.closest_enclosing(name, RecordType::Soa)?.first().cloned()
+    }
+
+    /// Everything in the zone whose apex is `apex`, as a full zone transfer (AXFR) sends it: the SOA first, then
+    /// every other record at or below the apex, grouped by name and type. Names inside another zone held here
+    /// (one with its own SOA further down) are left to that zone. None when `apex` has no SOA
+    pub fn zone_contents(&self, apex: &str) -> Option<Vec<ResourceRecord>> {
+
+        let apex = normalize_name(apex);
+        let soa = self.records.get(&(apex.clone(), RecordType::Soa))?.first()?.clone();
+
+        let mut keys: Vec<&(String, RecordType)> = self.records.keys()
+            .filter(|(name, record_type)| *record_type != RecordType::Soa || *name != apex)
+            .filter(|(name, _)| {
+                let owning_soa = self.closest_enclosing(name, RecordType::Soa).and_then(|records| records.first());
+                owning_soa.is_some_and(|owning_soa| normalize_name(&owning_soa.name) == apex)
+            })
+            .collect();
+        keys.sort_by_key(|(name, record_type)| (name.clone(), u16::from(*record_type)));
+
+        let mut contents = vec![soa];
+        contents.extend(keys.into_iter().flat_map(|key| self.records[key].iter().cloned()));
+
+        Some(contents)
+    }
+
+    /// Records of the given type at the closest ancestor of `name` (or the name itself) that owns any
+    fn closest_enclosing(&self, name: &str, record_type: RecordType) -> Option<&Vec<ResourceRecord>> {
+
+        let name = normalize_name(name);
+        let mut ancestor = name.as_str();
+
+        loop {
+            if let Some(records) = self.records.get(&(ancestor.to_string(), record_type)) {
+                return Some(records);
+            }
+
+            ancestor = ancestor.split_once('.')?.1;
+        }
+    }
+
+    /// Exact existence, ignoring wildcards
+    fn name_exists(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    /// Find the wildcard owner that covers a non-existent name, following RFC 4592 section 3.3.1: walk up to the
+    /// closest encloser (the nearest ancestor that exists) and use `*.<closest encloser>` if that is in the store.
+    /// A wildcard therefore matches one or more labels, but never a name that exists or sits below an existing name
+    fn wildcard_for(&self, name: &str) -> Option<String> {
+
+        let mut ancestor = name;
+
+        while let Some((_, parent)) = ancestor.split_once('.') {
+            if self.name_exists(parent) {
+                let wildcard = format!("*.{}", parent);
+                return self.names.contains(&wildcard).then_some(wildcard);
+            }
+            ancestor = parent;
+        }
+
+        None
+    }
+
+    /// Records owned by exactly this (normalized) name. Type ANY gathers every type the name has, ordered by type number
+    fn records_at(&self, owner: &str, record_type: RecordType) -> Vec<ResourceRecord> {
+
+        if record_type != RecordType::Any {
+            return self.records.get(&(owner.to_string(), record_type)).cloned().unwrap_or_default();
+        }
+
+        let mut types: Vec<RecordType> = self.records.keys()
+            .filter(|(stored_name, _)| stored_name == owner)
+            .map(|&(_, stored_type)| stored_type)
+            .collect();
+        types.sort_by_key(|&stored_type| u16::from(stored_type));
+
+        types.into_iter()
+            .flat_map(|stored_type| self.records[&(owner.to_string(), stored_type)].clone())
+            .collect()
+    }
+
+    /// Copy the wildcard's records of the requested type, with the owner name replaced by the queried name
+    fn synthesize_from_wildcard(&self, name: &str, normalized: &str, record_type: RecordType) -> Vec<ResourceRecord> {
+
+        let Some(wildcard) = self.wildcard_for(normalized) else {
+            return Vec::new();
+        };
+
+        let mut synthesized = self.records_at(&wildcard, record_type);
+        for record in &mut synthesized {
+            record.name = name.to_string();
+        }
+
+        synthesized
+    }
+}
+
+impl Default for ZoneStore {
+    fn default() -> ZoneStore {
+        ZoneStore::new()
+    }
+}
+
+
+/// Read a zone file TTL: plain seconds ("3600") or one or more number+unit pairs with units s, m, h, d and w in
+/// either case ("1h", "1h30m", "1W2D"). Units cannot be mixed with a bare trailing number
+pub fn parse_ttl(text: &str) -> Result<u32, DnsError> {
+
+    let invalid = || DnsError::InvalidTtl(text.to_string());
+
+    if !text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit()) {
+        return text.parse().map_err(|_| invalid());
+    }
+
+    let mut total: u32 = 0;
+    let mut number: Option<u32> = None;
+
+    for character in text.chars() {
+        if let Some(digit) = character.to_digit(10) {
+            number = Some(number.unwrap_or(0).checked_mul(10).and_then(|value| value.checked_add(digit)).ok_or_else(invalid)?);
+            continue;
+        }
+
+        let unit_seconds = match character.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3_600,
+            'd' => 86_400,
+            'w' => 604_800,
+            _ => return Err(invalid()),
+        };
+
+        let seconds = number.take().ok_or_else(invalid)?.checked_mul(unit_seconds).ok_or_else(invalid)?;
+        total = total.checked_add(seconds).ok_or_else(invalid)?;
+    }
+
+    // Empty text, or digits left over without a unit
+    if text.is_empty() || number.is_some() {
+        return Err(invalid());
+    }
+
+    Ok(total)
+}
+
+/// One DNSKEY or DS line of a keyset file, see ZoneStore::load_keyset
+fn parse_keyset_line(line: &str) -> Result<ResourceRecord, DnsError> {
+
+    let mut tokens = line.split_whitespace().peekable();
+
+    let mut record = ResourceRecord::new();
+    record.name = tokens.next().ok_or(DnsError::InvalidRdata)?.trim_end_matches('.').to_string();
+    record.ttl = DEFAULT_KEYSET_TTL;
+
+    // Optional TTL and class, in either order, before the type
+    while let Some(token) = tokens.next_if(|token| token.parse::<RecordType>().is_err()) {
+        match token.parse::<DnsClass>() {
+            Ok(class) => record.class = class.into(),
+            Err(_) => record.ttl = parse_ttl(token)?,
+        }
+    }
+
+    let record_type: RecordType = tokens.next().ok_or(DnsError::InvalidRdata)?.parse()?;
+    let fields: Vec<&str> = tokens.collect();
+
+    let mut rdata = Vec::new();
+    match record_type {
+        RecordType::Dnskey => {
+            let flags: u16 = parse_field(&fields, 0)?;
+            rdata.extend_from_slice(&flags.to_be_bytes());
+            rdata.push(parse_field(&fields, 1)?);     // Protocol, always 3
+            rdata.push(parse_field(&fields, 2)?);     // Algorithm
+            rdata.extend(decode_base64(&fields.get(3..).unwrap_or_default().concat())?);
+        }
+        RecordType::Ds => {
+            let key_tag: u16 = parse_field(&fields, 0)?;
+            rdata.extend_from_slice(&key_tag.to_be_bytes());
+            rdata.push(parse_field(&fields, 1)?);     // Algorithm
+            rdata.push(parse_field(&fields, 2)?);     // Digest type
+            rdata.extend(decode_hex(&fields.get(3..).unwrap_or_default().concat())?);
+        }
+        _ => return Err(DnsError::UnknownMnemonic(record_type.to_string())),
+    }
+
+    record.record_type = record_type.into();
+    record.rdata = RData::parse(record_type, &rdata)?;
+
+    Ok(record)
+}
+
+fn parse_field<T: std::str::FromStr>(fields: &[&str], index: usize) -> Result<T, DnsError> {
+    fields.get(index).and_then(|field| field.parse().ok()).ok_or(DnsError::InvalidRdata)
+}
+
+/// Standard base64 with padding, as DNSKEY public keys are written - RFC 4648 section 4
+fn decode_base64(text: &str) -> Result<Vec<u8>, DnsError> {
+
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+
+    for byte in text.bytes().take_while(|&byte| byte != b'=') {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(DnsError::InvalidRdata),
+        };
+
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        }
+    }
+
+    Ok(decoded)
+}
+
+fn decode_hex(text: &str) -> Result<Vec<u8>, DnsError> {
+
+    if !text.len().is_multiple_of(2) {
+        return Err(DnsError::InvalidRdata);
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2).ok_or(DnsError::InvalidRdata)?, 16).map_err(|_| DnsError::InvalidRdata))
+        .collect()
+}
+
+/// Names are compared case-insensitively and with or without the trailing root dot
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, record_type: RecordType, record_data: Vec<u8>) -> ResourceRecord {
+        let mut record = ResourceRecord::new();
+        record.name = name.to_string();
+        record.record_type = record_type.into();
+        record.class = DnsClass::In.into();
+        record.ttl = 300;
+        record.rdata = RData::parse(record_type, &record_data).unwrap();
+        record
+    }
+
+    fn three_addresses(round_robin: bool) -> ZoneStore {
+        let mut zone = ZoneStore::new();
+        zone.round_robin = round_robin;
+        for last_octet in 1..=3 {
+            zone.insert(record("www.example.com", RecordType::A, vec![192, 0, 2, last_octet]));
+        }
+        zone
+    }
+
+    #[test]
+    fn round_robin_rotates_consecutive_lookups() {
+        let zone = three_addresses(true);
+
+        let first = zone.lookup("www.example.com", RecordType::A, DnsClass::In);
+        let second = zone.lookup("www.example.com", RecordType::A, DnsClass::In);
+
+        assert_eq!(first.len(), 3);
+        assert_eq!(second.len(), 3);
+        assert_ne!(first[0].rdata, second[0].rdata);
+    }
+
+    #[test]
+    fn wildcard_stands_in_for_missing_names_only() {
+        let mut zone = ZoneStore::new();
+        zone.insert(record("*.example.com", RecordType::A, vec![192, 0, 2, 1]));
+        zone.insert(record("www.example.com", RecordType::A, vec![192, 0, 2, 2]));
+
+        let direct = zone.lookup("www.example.com", RecordType::A, DnsClass::In);
+        assert_eq!(direct.len(), 1);
+        assert_eq!(direct[0].rdata.encode(), vec![192, 0, 2, 2]);
+
+        let synthesized = zone.lookup("ftp.example.com", RecordType::A, DnsClass::In);
+        assert_eq!(synthesized.len(), 1);
+        assert_eq!(synthesized[0].name, "ftp.example.com");
+        assert_eq!(synthesized[0].rdata.encode(), vec![192, 0, 2, 1]);
+
+        assert!(zone.lookup("ftp.example.org", RecordType::A, DnsClass::In).is_empty());
+        assert!(zone.lookup("host.www.example.com", RecordType::A, DnsClass::In).is_empty());
+    }
+
+    #[test]
+    fn parse_ttl_accepts_seconds_and_units() {
+        assert_eq!(parse_ttl("3600").unwrap(), 3600);
+        assert_eq!(parse_ttl("1h").unwrap(), 3600);
+        assert_eq!(parse_ttl("1h30m").unwrap(), 5400);
+        assert_eq!(parse_ttl("1W2d").unwrap(), 777_600);
+
+        for invalid in ["", "h", "1h30", "1x", "99999999999"] {
+            assert!(parse_ttl(invalid).is_err(), "{:?} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn keyset_dnskey_is_served_from_the_store() {
+        let path = std::env::temp_dir().join(format!("dns_r-keyset-{}.key", std::process::id()));
+        fs::write(&path, "; zone signing key\nexample.com. 7200 IN DNSKEY 256 3 13 AQID\nexample.com. IN DS 12345 13 2 0a0b\n").unwrap();
+
+        let mut zone = ZoneStore::new();
+        let loaded = zone.load_keyset(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), 2);
+
+        let keys = zone.lookup("example.com", RecordType::Dnskey, DnsClass::In);
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].ttl, 7200);
+        assert_eq!(keys[0].rdata.encode(), vec![0x01, 0x00, 3, 13, 1, 2, 3]);
+
+        let delegation = zone.lookup("example.com", RecordType::Ds, DnsClass::In);
+        assert_eq!(delegation[0].ttl, DEFAULT_KEYSET_TTL);
+        assert_eq!(delegation[0].rdata.encode(), vec![0x30, 0x39, 13, 2, 0x0a, 0x0b]);
+    }
+
+    #[test]
+    fn type_any_and_class_any_are_independent() {
+        let mut zone = ZoneStore::new();
+        zone.insert(record("example.com", RecordType::A, vec![192, 0, 2, 1]));
+        zone.insert(record("example.com", RecordType::Mx, vec![0, 10, 0]));
+        let mut chaos = record("example.com", RecordType::A, vec![192, 0, 2, 2]);
+        chaos.class = DnsClass::Ch.into();
+        zone.insert(chaos);
+
+        let every_type = zone.lookup("example.com", RecordType::Any, DnsClass::In);
+        assert_eq!(every_type.len(), 2);
+        assert_eq!(RecordType::from(every_type[0].record_type), RecordType::A);
+        assert_eq!(RecordType::from(every_type[1].record_type), RecordType::Mx);
+
+        let every_class = zone.lookup("example.com", RecordType::A, DnsClass::Any);
+        assert_eq!(every_class.len(), 2);
+        assert!(every_class.iter().all(|record| RecordType::from(record.record_type) == RecordType::A));
+    }
+
+    #[test]
+    fn without_round_robin_order_is_insertion_order_every_time() {
+        let zone = three_addresses(false);
+        let last_octets = || -> Vec<u8> {
+            zone.lookup("www.example.com", RecordType::A, DnsClass::In).iter().map(|record| record.rdata.encode()[3]).collect()
+        };
+
+        for _ in 0..5 {
+            assert_eq!(last_octets(), vec![1, 2, 3]);
+        }
+    }
+
+    #[test]
+    fn empty_non_terminals_exist_and_block_wildcards() {
+        let mut zone = ZoneStore::new();
+        zone.insert(record("*.example.com", RecordType::A, vec![192, 0, 2, 1]));
+        zone.insert(record("host.lab.example.com", RecordType::A, vec![192, 0, 2, 2]));
+
+        // lab.example.com owns nothing, but a name below it does: it exists, so the wildcard does not cover it
+        assert!(zone.has_name("lab.example.com"));
+        assert!(zone.lookup("lab.example.com", RecordType::A, DnsClass::In).is_empty());
+        assert!(!zone.has_name("other.lab.example.com"));
+
+        assert!(zone.has_name("ftp.example.com"));
+        assert_eq!(zone.lookup("ftp.example.com", RecordType::A, DnsClass::In).len(), 1);
+    }
+}